@@ -1,8 +1,60 @@
-use super::config::{AppConfig, OutputFormat};
-use clap::{Arg, ArgAction, Command};
+use super::config::{default_config_path, AppConfig};
+use super::exit_code;
+use super::logging;
+use clap::builder::PossibleValuesParser;
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use srun_cli::srun::connectivity::{Probe, Quorum};
+use std::net::Ipv6Addr;
+
+// Command line options and the configuration keys they set
+const GLOBAL_OPTIONS: &[(&str, &str)] = &[
+    ("username", "username"),
+    ("password", "password"),
+    ("password-stdin", "password_stdin"),
+    ("password-file", "password_file"),
+    ("server", "server"),
+    ("redirect", "redirect"),
+    ("redirect-host", "redirect_host"),
+    ("probe", "probes"),
+    ("quorum", "quorum"),
+    ("quick-abort", "quick_abort"),
+    ("interface", "interface"),
+    ("output", "output"),
+    ("connect-timeout", "connect_timeout"),
+    ("timeout", "timeout"),
+    ("retries", "retries"),
+    ("retry-backoff", "retry_backoff"),
+    ("retry-on", "retry_on"),
+    ("dual-stack", "dual_stack"),
+    ("ipv6-server", "ipv6_server"),
+    ("ipv6-source", "ipv6_source"),
+    ("ac-id", "ac_id"),
+    ("default-ac-id", "default_ac_id"),
+    ("quiet", "quiet"),
+    ("log-target", "log_target"),
+    ("trace", "trace"),
+    ("trace-secrets", "trace_secrets"),
+    ("trace-file", "trace_file"),
+    ("record", "record"),
+    ("replay", "replay"),
+];
+
+const DAEMON_OPTIONS: &[(&str, &str)] = &[("interval", "interval"), ("max-backoff", "max_backoff")];
+
+// Flags and the options turning them off again, e.g. when the configuration file sets them
+const NEGATED_FLAGS: &[(&str, &str)] = &[
+    ("password-stdin", "no-password-stdin"),
+    ("redirect", "no-redirect"),
+    ("quick-abort", "no-quick-abort"),
+    ("dual-stack", "no-dual-stack"),
+    ("quiet", "no-quiet"),
+    ("trace", "no-trace"),
+    ("trace-secrets", "no-trace-secrets"),
+];
 
 pub fn process_cli() -> AppConfig {
-    let matches = Command::new("SRUN Client")
+    let mut command = Command::new("SRUN Client")
         .version("0.1.0")
         .author("SpartaEN <i@evo.moe>")
         .about("SRUN Commandline Client")
@@ -16,13 +68,24 @@ pub fn process_cli() -> AppConfig {
             Arg::new("password")
                 .short('p')
                 .long("password")
-                .help("Password for the client"),
+                .help("Password for the client, visible to other users in the process list"),
+        )
+        .arg(
+            Arg::new("password-stdin")
+                .long("password-stdin")
+                .action(ArgAction::SetTrue)
+                .help("Read the password from the first line of stdin"),
+        )
+        .arg(
+            Arg::new("password-file")
+                .long("password-file")
+                .help("Read the password from the first line of this file"),
         )
         .arg(
             Arg::new("server")
                 .short('s')
                 .long("server")
-                .help("Server url"),
+                .help("Server url, discovered from the captive-portal redirect when omitted"),
         )
         .arg(
             Arg::new("redirect")
@@ -36,21 +99,38 @@ pub fn process_cli() -> AppConfig {
         .arg(
             Arg::new("redirect-host")
                 .long("redirect-host")
-                .default_value("http://www.google.cn/generate_204")
-                .help("Site to trigger redirection"),
+                .help("Site to trigger redirection [default: http://www.google.cn/generate_204]"),
+        )
+        .arg(
+            Arg::new("probe")
+                .long("probe")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| s.parse::<Probe>())
+                .help("Connectivity probe, repeatable: http:[STATUS:]URL, body:URL TEXT, dns:HOST or tcp:HOST:PORT, the dns and tcp probes ignore --interface [default: http:204:<redirect-host>]"),
+        )
+        .arg(
+            Arg::new("quorum")
+                .long("quorum")
+                .value_parser(|s: &str| s.parse::<Quorum>())
+                .help("Probes that must pass to count as online: any, all, majority or a number [default: majority]"),
         )
         .arg(
             Arg::new("quick-abort")
                 .short('q')
                 .long("quick-abort")
                 .action(ArgAction::SetTrue)
-                .help("Abort login process if the client is already logged in, will enable --redirect"),
+                .help("Abort login process if the probes show the client is already online, will enable --redirect"),
         )
         .arg(
             Arg::new("config")
                 .short('c')
                 .long("config")
-                .help("Configuration file"),
+                .help("Configuration file, JSON or TOML by extension [default: $XDG_CONFIG_HOME/srun-cli/config.toml, then /etc/srun-cli/config.toml]"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Profile of the configuration file to use [default: default_profile, or the profile named default]"),
         )
         .arg(
             Arg::new("interface")
@@ -64,63 +144,267 @@ pub fn process_cli() -> AppConfig {
                 .long("output")
                 .help("Output format"),
         )
+        .arg(
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_parser(value_parser!(f64))
+                .help("Seconds to wait for a connection to the portal, 0 to wait forever [default: 10]"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_parser(value_parser!(f64))
+                .help("Seconds to wait for a whole request, 0 to wait forever [default: 30]"),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_parser(value_parser!(u32))
+                .help("Number of times a failed request is retried [default: 2]"),
+        )
+        .arg(
+            Arg::new("retry-backoff")
+                .long("retry-backoff")
+                .value_parser(value_parser!(f64))
+                .help("Seconds before the first retry, doubled for each following one [default: 1]"),
+        )
+        .arg(
+            Arg::new("retry-on")
+                .long("retry-on")
+                .help("Comma separated failures worth retrying: network, 5xx or none [default: network,5xx]"),
+        )
+        .arg(
+            Arg::new("dual-stack")
+                .long("dual-stack")
+                .action(ArgAction::SetTrue)
+                .help("Authenticate the IPv6 address too, as a separate session"),
+        )
+        .arg(
+            Arg::new("ipv6-server")
+                .long("ipv6-server")
+                .help("Server url for the IPv6 session [default: --server]"),
+        )
+        .arg(
+            Arg::new("ipv6-source")
+                .long("ipv6-source")
+                .value_parser(value_parser!(Ipv6Addr))
+                .help("IPv6 address to authenticate [default: first global address of --interface]"),
+        )
+        .arg(
+            Arg::new("ac-id")
+                .long("ac-id")
+                .help("Portal ac_id, skips discovering it"),
+        )
+        .arg(
+            Arg::new("default-ac-id")
+                .long("default-ac-id")
+                .help("ac_id to use when discovery finds none"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .help("Log more diagnostics, repeat for debug (-vv) and trace (-vvv) output"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .help("Only log errors"),
+        )
+        .arg(
+            Arg::new("log-target")
+                .long("log-target")
+                .value_parser(PossibleValuesParser::new(logging::TARGETS))
+                .help("Where diagnostics are logged [default: stderr]"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .action(ArgAction::SetTrue)
+                .help("Write every portal request and response to stderr, with secrets redacted"),
+        )
+        .arg(
+            Arg::new("trace-secrets")
+                .long("trace-secrets")
+                .action(ArgAction::SetTrue)
+                .help("Do not redact the password hash, info and chksum in the trace"),
+        )
+        .arg(
+            Arg::new("trace-file")
+                .long("trace-file")
+                .help("Write the trace to this file instead of stderr, implies --trace"),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .help("Record every portal exchange, with secrets redacted, into this cassette file"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .help("Answer portal requests from this cassette file instead of the network"),
+        )
         .subcommand(Command::new("query").about("Query account status"))
-        .subcommand(Command::new("login").about("Log in to campus network"))
+        .subcommand(
+            Command::new("login").about("Log in to campus network").arg(
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .action(ArgAction::SetTrue)
+                    .help("Print the login request with its computed parameters instead of sending it"),
+            ),
+        )
         .subcommand(Command::new("logout").about("Log out from campus network"))
-        .get_matches();
-
-    let username = matches.get_one::<String>("username");
-    let password = matches.get_one::<String>("password");
-    let server = matches.get_one::<String>("server");
-    let redirect = matches.get_flag("redirect");
-    let redirect_host = matches.get_one::<String>("redirect-host");
-    let config_path = matches.get_one::<String>("config");
-    let interface = matches.get_one::<String>("interface");
-    let output = matches
-        .get_one::<String>("output")
-        .map(|s| s.to_lowercase());
-    let quick_abort = matches.get_flag("quick-abort");
+        .subcommand(
+            Command::new("check")
+                .about("Run the connectivity probes, exit with 0 when online and 1 otherwise"),
+        )
+        .subcommand(
+            Command::new("discover")
+                .about("Find the portal server and ac_id through the captive-portal redirect"),
+        )
+        .subcommand(
+            Command::new("profiles")
+                .about("Manage the profiles of the configuration file")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the profiles")),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Keep the session online, logging in again whenever it drops")
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_parser(value_parser!(u64))
+                        .help("Seconds between status checks [default: 60]"),
+                )
+                .arg(
+                    Arg::new("max-backoff")
+                        .long("max-backoff")
+                        .value_parser(value_parser!(u64))
+                        .help("Upper bound in seconds for the delay after failed logins [default: 900]"),
+                ),
+        );
+    for (flag, negated) in NEGATED_FLAGS {
+        command = command.arg(
+            Arg::new(*negated)
+                .long(*negated)
+                .action(ArgAction::SetTrue)
+                .overrides_with(*flag)
+                .hide(true),
+        );
+    }
+    let matches = command.get_matches();
 
-    let mut app_config: AppConfig;
-    match config_path {
-        Some(config_path) => {
-            app_config = AppConfig::from_file(config_path);
+    // Later layers win: built-in defaults, configuration file, profile, environment, command line
+    let mut app_config = AppConfig::new();
+    let config_path = matches
+        .get_one::<String>("config")
+        .cloned()
+        .or_else(|| default_config_path().map(|path| path.to_string_lossy().into_owned()));
+    if let Some(config_path) = config_path {
+        if let Err(e) = app_config.load_file(&config_path) {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::CONFIG_ERROR);
         }
-        None => {
-            app_config = AppConfig::new();
-            app_config.username = username.map(|s| s.clone());
-            app_config.password = password.map(|s| s.clone());
-            app_config.server = server.map(|s| s.clone());
-            app_config.redirect = redirect;
-            app_config.redirect_host = redirect_host.map(|s| s.clone());
-            app_config.interface = interface.map(|s| s.clone());
-            app_config.output = match output.as_deref() {
-                Some("json") => OutputFormat::Json,
-                _ => OutputFormat::Plain,
-            };
-            app_config.quick_abort = quick_abort;
+    }
+    let profile = matches
+        .get_one::<String>("profile")
+        .cloned()
+        .or_else(|| std::env::var("SRUN_PROFILE").ok());
+    if let Err(e) = app_config.apply_profile(profile.as_deref()) {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::CONFIG_ERROR);
+    }
+    match app_config.load_env(std::env::vars()) {
+        // Logging is not set up before the configuration is complete
+        Ok(ignored) => {
+            for name in ignored {
+                eprintln!("Ignoring {name}, it names no configuration key");
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::CONFIG_ERROR);
         }
     }
-
-    match app_config.server {
-        Some(_) => {}
-        None => {
-            println!("Server must be provided");
-            std::process::exit(1);
+    let mut overrides = cli_overrides(&matches, GLOBAL_OPTIONS);
+    for (flag, negated) in NEGATED_FLAGS {
+        if matches.get_flag(negated) {
+            let key = GLOBAL_OPTIONS.iter().find(|(id, _)| id == flag).unwrap().1;
+            overrides.push((key, vec![String::from("false")]));
+        }
+    }
+    if let Some(("daemon", daemon_matches)) = matches.subcommand() {
+        overrides.extend(cli_overrides(daemon_matches, DAEMON_OPTIONS));
+    }
+    // A count has no raw value to pass on
+    if matches.value_source("verbose") == Some(ValueSource::CommandLine) {
+        overrides.push(("verbose", vec![matches.get_count("verbose").to_string()]));
+    }
+    for (key, values) in overrides {
+        if let Err(e) = app_config.set_list(key, &values) {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::USAGE_ERROR);
         }
     }
 
+    if let Err(e) = app_config
+        .open_trace()
+        .and_then(|_| app_config.open_cassettes())
+    {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::CONFIG_ERROR);
+    }
+
+    // Only meant for a single invocation, so not a configuration key
+    if let Some(("login", login_matches)) = matches.subcommand() {
+        app_config.dry_run = login_matches.get_flag("dry-run");
+    }
+
     match matches.subcommand_name() {
         Some(some) => app_config.command = Some(String::from(some)),
         None => {
-            println!("Command must be provided");
-            std::process::exit(1);
+            eprintln!("Command must be provided");
+            std::process::exit(exit_code::USAGE_ERROR);
         }
     }
 
+    // Without probes of its own the redirect host is the single probe
+    if let Err(e) = app_config.quorum.validate(app_config.probes.len().max(1)) {
+        eprintln!("{}", e);
+        std::process::exit(exit_code::CONFIG_ERROR);
+    }
+
+    if app_config.interval == 0 {
+        eprintln!("Check interval must be greater than 0");
+        std::process::exit(exit_code::USAGE_ERROR);
+    }
+
     if app_config.quick_abort {
         app_config.redirect = true;
     }
 
     app_config
 }
+
+/// Options given on the command line, as configuration key and raw values. Flags count as
+/// `true`, repeated options give one value each.
+fn cli_overrides(
+    matches: &ArgMatches,
+    options: &[(&str, &'static str)],
+) -> Vec<(&'static str, Vec<String>)> {
+    options
+        .iter()
+        .filter(|(id, _)| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .filter_map(|(id, key)| {
+            let values: Vec<String> = matches
+                .get_raw(id)?
+                .map(|v| v.to_string_lossy().into_owned())
+                .collect();
+            Some((*key, values))
+        })
+        .collect()
+}
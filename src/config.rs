@@ -1,7 +1,24 @@
-use crate::srun::client::{SRUNClient, SRUNClientCredentials, SRUNClientOptions};
-use json;
+use super::logging::LogTarget;
+use srun_cli::srun::cassette::{Cassette, CassetteRecorder, RecordingTransport, ReplayTransport};
+use srun_cli::srun::client::{
+    EndpointPaths, Ipv6Options, PortalProfile, SRUNClient, SRUNClientCredentials, SRUNClientOptions,
+};
+use srun_cli::srun::connectivity::{ConnectivityChecker, ConnectivityReport, Probe, Quorum};
+use srun_cli::srun::discovery::{self, PortalDiscovery};
+use srun_cli::srun::error::SRUNClientError;
+use srun_cli::srun::secret::SecretString;
+use srun_cli::srun::trace::{TraceSink, TraceTransport};
+use srun_cli::srun::transport::{ReqwestTransport, Transport};
+use std::env;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::net::Ipv6Addr;
 use std::option::Option;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 #[derive(Debug)]
 pub enum OutputFormat {
@@ -9,10 +26,35 @@ pub enum OutputFormat {
     Json,
 }
 
+/// Named set of settings from the `profiles` section of the configuration file, e.g. one per
+/// campus or account.
+#[derive(Debug)]
+pub struct Profile {
+    pub name: String,
+    /// Kept apart from the raw values, and moved out when the profile is applied.
+    pub password: Option<SecretString>,
+    /// Configuration keys and raw values, applied through [`AppConfig::set_list`].
+    pub settings: Vec<(String, Vec<String>)>,
+}
+
+impl Profile {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.first())
+            .map(String::as_str)
+    }
+}
+
 #[derive(Debug)]
 pub struct AppConfig {
     pub username: Option<String>,
-    pub password: Option<String>,
+    pub password: Option<SecretString>,
+    pub password_stdin: bool,
+    pub password_file: Option<String>,
+    pub password_command: Option<String>,
     pub server: Option<String>,
     pub redirect: bool,
     pub redirect_host: Option<String>,
@@ -20,6 +62,45 @@ pub struct AppConfig {
     pub output: OutputFormat,
     pub command: Option<String>,
     pub quick_abort: bool,
+    /// `login --dry-run`
+    pub dry_run: bool,
+    pub interval: u64,
+    pub max_backoff: u64,
+    pub connect_timeout: f64,
+    pub timeout: f64,
+    pub retries: u32,
+    pub retry_backoff: f64,
+    pub retry_on_network: bool,
+    pub retry_on_server_error: bool,
+    pub dual_stack: bool,
+    pub ipv6_server: Option<String>,
+    pub ipv6_source: Option<Ipv6Addr>,
+    pub portal: PortalProfile,
+    pub endpoints: EndpointPaths,
+    pub ac_id: Option<String>,
+    /// `ac_id` found along with the server by [`AppConfig::discover`], not a setting.
+    pub discovered_ac_id: Option<String>,
+    pub default_ac_id: Option<String>,
+    /// Number of `-v` given, raising the log level from warnings.
+    pub verbose: u8,
+    pub quiet: bool,
+    pub log_target: LogTarget,
+    pub trace: bool,
+    pub trace_secrets: bool,
+    pub trace_file: Option<String>,
+    /// Opened from the trace settings by [`open_trace`](AppConfig::open_trace).
+    pub trace_sink: Option<TraceSink>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    /// Opened from `record` and `replay` by [`open_cassettes`](AppConfig::open_cassettes).
+    pub recorder: Option<CassetteRecorder>,
+    pub replayer: Option<Arc<ReplayTransport>>,
+    pub probes: Vec<Probe>,
+    pub quorum: Quorum,
+    pub profiles: Vec<Profile>,
+    pub default_profile: Option<String>,
+    /// Name of the profile that was applied, if any.
+    pub profile: Option<String>,
 }
 
 impl AppConfig {
@@ -27,6 +108,9 @@ impl AppConfig {
         AppConfig {
             username: None,
             password: None,
+            password_stdin: false,
+            password_file: None,
+            password_command: None,
             server: None,
             redirect: false,
             redirect_host: None,
@@ -34,61 +118,967 @@ impl AppConfig {
             output: OutputFormat::Plain,
             command: None,
             quick_abort: false,
+            dry_run: false,
+            interval: 60,
+            max_backoff: 900,
+            connect_timeout: 10.0,
+            timeout: 30.0,
+            retries: 2,
+            retry_backoff: 1.0,
+            retry_on_network: true,
+            retry_on_server_error: true,
+            dual_stack: false,
+            ipv6_server: None,
+            ipv6_source: None,
+            portal: PortalProfile::default(),
+            endpoints: EndpointPaths::default(),
+            ac_id: None,
+            discovered_ac_id: None,
+            default_ac_id: None,
+            verbose: 0,
+            quiet: false,
+            log_target: LogTarget::default(),
+            trace: false,
+            trace_secrets: false,
+            trace_file: None,
+            trace_sink: None,
+            record: None,
+            replay: None,
+            recorder: None,
+            replayer: None,
+            probes: vec![],
+            quorum: Quorum::default(),
+            profiles: vec![],
+            default_profile: None,
+            profile: None,
         }
     }
 
-    pub fn from_file(config_path: &String) -> AppConfig {
-        let mut app_config = AppConfig::new();
-        let content = fs::read_to_string(config_path);
-        match content {
-            Ok(s) => {
-                let json = json::parse(s.as_str()).unwrap();
-                app_config.username = json["username"].as_str().map(String::from);
-                app_config.password = json["password"].as_str().map(String::from);
-                app_config.server = json["server"].as_str().map(String::from);
-                app_config.redirect = json["redirect"].as_bool().unwrap_or(false);
-                app_config.redirect_host = json["redirect_host"].as_str().map(String::from);
-                app_config.interface = json["interface"].as_str().map(String::from);
-                app_config.output = match json["output"].as_str() {
-                    Some("json") => OutputFormat::Json,
-                    _ => OutputFormat::Plain,
-                };
-                app_config.quick_abort = json["quick_abort"].as_bool().unwrap_or(false);
-                app_config
+    /// Parses a comma separated list of retryable failure kinds: `network`, `5xx` or `none`.
+    pub fn set_retry_on(&mut self, kinds: &str) -> Result<(), String> {
+        self.retry_on_network = false;
+        self.retry_on_server_error = false;
+        for kind in kinds
+            .split([',', ';'])
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+        {
+            match kind {
+                "network" => self.retry_on_network = true,
+                "5xx" => self.retry_on_server_error = true,
+                "none" => {}
+                _ => return Err(format!("Unknown retry condition {kind}")),
             }
-            Err(_) => {
-                println!("Failed to read config file");
-                std::process::exit(1);
+        }
+        Ok(())
+    }
+
+    /// Sets a key given as a list, from an array in a configuration file or a repeated command
+    /// line option. Each item is taken whole, unlike the `;` separated form [`set`](Self::set)
+    /// accepts from environment variables.
+    pub fn set_list(&mut self, key: &str, values: &[String]) -> Result<(), String> {
+        if let Some(rest) = key.strip_prefix("profiles.") {
+            return self.add_profile_setting(rest, values);
+        }
+        match key {
+            "probes" => {
+                self.probes = values
+                    .iter()
+                    .map(|value| value.trim().parse())
+                    .collect::<Result<_, _>>()?
             }
+            "retry_on" => self.set_retry_on(&values.join(","))?,
+            _ => match values {
+                [value] => self.set(key, value)?,
+                _ => return Err(format!("Expected a single value for {key}")),
+            },
         }
+        Ok(())
     }
+
+    /// Sets the field named by configuration key `key` (e.g. `interval` or `portal.n`) from
+    /// its string form.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = |kind: &str| format!("Invalid {kind} for {key}: {value}");
+        match key {
+            "username" => self.username = Some(value.to_string()),
+            "password" => self.password = Some(value.into()),
+            "password_stdin" => {
+                self.password_stdin = parse_bool(value).ok_or_else(|| invalid("boolean"))?
+            }
+            "password_file" => self.password_file = Some(value.to_string()),
+            "password_command" => self.password_command = Some(value.to_string()),
+            "server" => self.server = Some(value.to_string()),
+            "redirect" => self.redirect = parse_bool(value).ok_or_else(|| invalid("boolean"))?,
+            "redirect_host" => self.redirect_host = Some(value.to_string()),
+            "interface" => self.interface = Some(value.to_string()),
+            "output" => {
+                self.output = match value.to_lowercase().as_str() {
+                    "json" => OutputFormat::Json,
+                    "plain" => OutputFormat::Plain,
+                    _ => return Err(invalid("output format")),
+                }
+            }
+            "quick_abort" => {
+                self.quick_abort = parse_bool(value).ok_or_else(|| invalid("boolean"))?
+            }
+            "interval" => self.interval = value.parse().map_err(|_| invalid("number"))?,
+            "max_backoff" => self.max_backoff = value.parse().map_err(|_| invalid("number"))?,
+            "connect_timeout" => {
+                self.connect_timeout = value.parse().map_err(|_| invalid("number"))?
+            }
+            "timeout" => self.timeout = value.parse().map_err(|_| invalid("number"))?,
+            "retries" => self.retries = value.parse().map_err(|_| invalid("number"))?,
+            "retry_backoff" => self.retry_backoff = value.parse().map_err(|_| invalid("number"))?,
+            "retry_on" => self.set_retry_on(value)?,
+            "dual_stack" => {
+                self.dual_stack = parse_bool(value).ok_or_else(|| invalid("boolean"))?
+            }
+            "ipv6_server" => self.ipv6_server = Some(value.to_string()),
+            "ipv6_source" => {
+                self.ipv6_source = Some(value.parse().map_err(|_| invalid("IPv6 address"))?)
+            }
+            "ac_id" => self.ac_id = Some(value.to_string()),
+            "default_ac_id" => self.default_ac_id = Some(value.to_string()),
+            // `verbose = true` in a file is the same as a single -v
+            "verbose" => {
+                self.verbose = match parse_bool(value) {
+                    Some(verbose) => verbose.into(),
+                    None => value.parse().map_err(|_| invalid("verbosity"))?,
+                }
+            }
+            "quiet" => self.quiet = parse_bool(value).ok_or_else(|| invalid("boolean"))?,
+            "log_target" => self.log_target = value.parse()?,
+            "trace" => self.trace = parse_bool(value).ok_or_else(|| invalid("boolean"))?,
+            "trace_secrets" => {
+                self.trace_secrets = parse_bool(value).ok_or_else(|| invalid("boolean"))?
+            }
+            "trace_file" => self.trace_file = Some(value.to_string()),
+            "record" => self.record = Some(value.to_string()),
+            "replay" => self.replay = Some(value.to_string()),
+            // Probes may contain commas and spaces, so an environment variable separates them
+            // with semicolons
+            "probes" => {
+                self.probes = value
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?
+            }
+            "quorum" => self.quorum = value.parse()?,
+            "portal.enc_ver" => self.portal.enc_ver = value.to_string(),
+            "portal.n" => self.portal.n = value.to_string(),
+            "portal.type" => self.portal.login_type = value.to_string(),
+            "portal.os" => self.portal.os = value.to_string(),
+            "portal.name" => self.portal.name = value.to_string(),
+            "portal.callback" => self.portal.callback = value.to_string(),
+            "portal.double_stack" => {
+                self.portal.double_stack = parse_bool(value).ok_or_else(|| invalid("boolean"))?
+            }
+            "endpoints.rad_user_info" => self.endpoints.rad_user_info = value.to_string(),
+            "endpoints.get_challenge" => self.endpoints.get_challenge = value.to_string(),
+            "endpoints.srun_portal" => self.endpoints.srun_portal = value.to_string(),
+            "endpoints.index" => self.endpoints.index = value.to_string(),
+            "default_profile" => self.default_profile = Some(value.to_string()),
+            _ => match key.strip_prefix("profiles.") {
+                Some(rest) => self.add_profile_setting(rest, &[value.to_string()])?,
+                None => return Err(unknown_key(key)),
+            },
+        }
+        Ok(())
+    }
+
+    // Profiles are only collected here, `apply_profile` sets their keys later. The keys are
+    // checked right away, so a typo shows up even in a profile that is not in use.
+    fn add_profile_setting(&mut self, name_and_key: &str, values: &[String]) -> Result<(), String> {
+        let (name, key) = name_and_key
+            .split_once('.')
+            .ok_or_else(|| format!("Profile {name_and_key} must be a table of settings"))?;
+        if key.starts_with("profiles.") || key == "default_profile" {
+            return Err(format!("Profile {name}: {key} cannot be set in a profile"));
+        }
+        let password = match (key, values) {
+            ("password", [password]) => Some(SecretString::from(password.as_str())),
+            ("password", _) => return Err(format!("Profile {name}: Expected a single password")),
+            _ => {
+                AppConfig::new()
+                    .set_list(key, values)
+                    .map_err(|e| format!("Profile {name}: {e}"))?;
+                None
+            }
+        };
+        let index = match self.profiles.iter().position(|p| p.name == name) {
+            Some(index) => index,
+            None => {
+                self.profiles.push(Profile {
+                    name: name.to_string(),
+                    password: None,
+                    settings: vec![],
+                });
+                self.profiles.len() - 1
+            }
+        };
+        let profile = &mut self.profiles[index];
+        match password {
+            Some(password) => profile.password = Some(password),
+            None => profile.settings.push((key.to_string(), values.to_vec())),
+        }
+        Ok(())
+    }
+
+    /// Applies the settings of a configuration file on top of the current ones. Files ending in
+    /// `.toml` are read as TOML, anything else as JSON.
+    pub fn load_file(&mut self, config_path: &str) -> Result<(), String> {
+        let content = fs::read_to_string(config_path)
+            .map_err(|e| format!("Failed to read config file {config_path}: {e}"))?;
+        let is_toml = Path::new(config_path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        if is_toml {
+            let table = content.parse::<toml::Table>().map_err(|e| {
+                let (line, column) = e
+                    .span()
+                    .map(|span| line_column(&content, span.start))
+                    .unwrap_or((1, 1));
+                format!(
+                    "{config_path}:{line}:{column}: {}",
+                    e.message().trim_end().replace('\n', ", ")
+                )
+            })?;
+            self.load_toml("", &table)
+        } else {
+            let json = json::parse(&content).map_err(|e| match e {
+                json::Error::UnexpectedCharacter { ch, line, column } => {
+                    format!("{config_path}:{line}:{column}: Unexpected character {ch:?}")
+                }
+                e => format!("{config_path}: {e}"),
+            })?;
+            self.load_json("", &json)
+        }
+        .map_err(|e| format!("{config_path}: {e}"))
+    }
+
+    fn load_json(&mut self, prefix: &str, json: &json::JsonValue) -> Result<(), String> {
+        for (key, value) in json.entries() {
+            let key = format!("{prefix}{key}");
+            if value.is_object() {
+                self.load_json(&format!("{key}."), value)?;
+            } else if value.is_array() {
+                let items: Vec<String> = value.members().map(json_scalar).collect();
+                self.set_list(&key, &items)?;
+            } else if !value.is_null() {
+                self.set(&key, &json_scalar(value))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn load_toml(&mut self, prefix: &str, table: &toml::Table) -> Result<(), String> {
+        for (key, value) in table {
+            let key = format!("{prefix}{key}");
+            match value {
+                toml::Value::Table(table) => self.load_toml(&format!("{key}."), table)?,
+                toml::Value::Array(items) => {
+                    let items: Vec<String> = items.iter().map(toml_scalar).collect();
+                    self.set_list(&key, &items)?;
+                }
+                value => self.set(&key, &toml_scalar(value))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the password from the first configured source: `--password-stdin`, `password`,
+    /// `password_file`, `password_command`, a `password` credential passed by systemd, and
+    /// finally a prompt if stdin is a terminal.
+    pub fn resolve_password(&mut self) -> Result<(), String> {
+        let credentials_dir = env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+        let terminal = io::stdin().is_terminal();
+        self.resolve_password_from(
+            &mut io::stdin().lock(),
+            credentials_dir.as_deref(),
+            terminal,
+        )
+    }
+
+    fn resolve_password_from(
+        &mut self,
+        stdin: &mut dyn BufRead,
+        credentials_dir: Option<&Path>,
+        terminal: bool,
+    ) -> Result<(), String> {
+        if self.password_stdin {
+            let mut line = Zeroizing::new(String::new());
+            stdin
+                .read_line(&mut line)
+                .map_err(|e| format!("Failed to read password from stdin: {e}"))?;
+            self.password = Some(first_line(&line, "stdin")?);
+            return Ok(());
+        }
+        if let Some(password) = &self.password {
+            if password.expose().is_empty() {
+                return Err(String::from("Empty password in the configuration"));
+            }
+            return Ok(());
+        }
+        if let Some(path) = &self.password_file {
+            let content = Zeroizing::new(
+                fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read password file {path}: {e}"))?,
+            );
+            self.password = Some(first_line(&content, &format!("password file {path}"))?);
+            return Ok(());
+        }
+        if let Some(command) = &self.password_command {
+            let output = shell(command)
+                .stderr(Stdio::inherit())
+                .output()
+                .map_err(|e| format!("Failed to run password command: {e}"))?;
+            if !output.status.success() {
+                return Err(format!("Password command failed with {}", output.status));
+            }
+            let stdout = Zeroizing::new(output.stdout);
+            self.password = Some(first_line(
+                &String::from_utf8_lossy(&stdout),
+                "password command",
+            )?);
+            return Ok(());
+        }
+        // systemd's LoadCredential=password:/path/to/file
+        if let Some(dir) = credentials_dir {
+            let path = dir.join("password");
+            if path.is_file() {
+                let content =
+                    Zeroizing::new(fs::read_to_string(&path).map_err(|e| {
+                        format!("Failed to read credential {}: {e}", path.display())
+                    })?);
+                self.password = Some(first_line(
+                    &content,
+                    &format!("credential {}", path.display()),
+                )?);
+                return Ok(());
+            }
+        }
+        if terminal {
+            let prompt = match &self.username {
+                Some(username) => format!("Password for {username}: "),
+                None => String::from("Password: "),
+            };
+            let password = Zeroizing::new(
+                rpassword::prompt_password(prompt)
+                    .map_err(|e| format!("Failed to read password: {e}"))?,
+            );
+            self.password = Some(first_line(&password, "prompt")?);
+        }
+        Ok(())
+    }
+
+    /// Sets up the wire trace if `trace` or `trace_file` is set, to stderr unless a file is given.
+    pub fn open_trace(&mut self) -> Result<(), String> {
+        let sink = match &self.trace_file {
+            Some(path) => TraceSink::new(
+                fs::File::create(path)
+                    .map_err(|e| format!("Failed to create trace file {path}: {e}"))?,
+            ),
+            None if self.trace => TraceSink::new(io::stderr()),
+            None => return Ok(()),
+        };
+        self.trace_sink = Some(sink.with_secrets(self.trace_secrets));
+        Ok(())
+    }
+
+    /// Starts recording to `record` and loads the session to replay from `replay`.
+    pub fn open_cassettes(&mut self) -> Result<(), String> {
+        if let Some(path) = &self.record {
+            self.recorder = Some(
+                CassetteRecorder::create(path)
+                    .map_err(|e| format!("Failed to create cassette {path}: {e}"))?,
+            );
+        }
+        if let Some(path) = &self.replay {
+            let cassette =
+                Cassette::load(path).map_err(|e| format!("Failed to load cassette {path}: {e}"))?;
+            self.replayer = Some(Arc::new(ReplayTransport::new(cassette)));
+        }
+        Ok(())
+    }
+
+    /// Name of the profile applied when none is given: `default_profile`, or else the profile
+    /// called `default` if there is one.
+    pub fn default_profile_name(&self) -> Option<&str> {
+        self.default_profile.as_deref().or_else(|| {
+            self.profiles
+                .iter()
+                .find(|p| p.name == "default")
+                .map(|p| p.name.as_str())
+        })
+    }
+
+    /// Applies the settings of profile `name`, or of the default profile if `name` is `None`.
+    pub fn apply_profile(&mut self, name: Option<&str>) -> Result<(), String> {
+        let name = match name.or(self.default_profile_name()) {
+            Some(name) => name.to_string(),
+            None => return Ok(()),
+        };
+        let profile = match self.profiles.iter_mut().find(|p| p.name == name) {
+            Some(profile) => profile,
+            None => return Err(format!("Unknown profile {name}")),
+        };
+        let password = profile.password.take();
+        let settings = profile.settings.clone();
+        for (key, values) in settings {
+            self.set_list(&key, &values)
+                .map_err(|e| format!("Profile {name}: {e}"))?;
+        }
+        if password.is_some() {
+            self.password = password;
+        }
+        self.profile = Some(name);
+        Ok(())
+    }
+
+    /// Applies `SRUN_*` environment variables, e.g. `SRUN_INTERFACE` or `SRUN_PORTAL_ENC_VER`.
+    /// Other tools may use the prefix too, so variables naming no key are skipped and returned.
+    pub fn load_env(
+        &mut self,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Vec<String>, String> {
+        let mut ignored = Vec::new();
+        for (name, value) in vars {
+            let key = match name.strip_prefix("SRUN_") {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            let key = match key.split_once('_') {
+                Some((section @ ("portal" | "endpoints"), field)) => format!("{section}.{field}"),
+                _ => key,
+            };
+            // SRUN_PROFILE picks the profile, which has been applied by now
+            if key == "profile" {
+                continue;
+            }
+            match self.set(&key, &value) {
+                Err(e) if e == unknown_key(&key) => ignored.push(name),
+                result => result.map_err(|e| format!("{name}: {e}"))?,
+            }
+        }
+        Ok(ignored)
+    }
+}
+
+// `sh -c`, or `cmd /C` on Windows where there is no sh
+fn shell(command: &str) -> Command {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = Command::new(program);
+    shell.args([flag, command]);
+    shell
+}
+
+fn unknown_key(key: &str) -> String {
+    format!("Unknown key {key}")
+}
+
+// Numbers and booleans are accepted in their JSON spelling, e.g. "ac_id": 7
+fn json_scalar(value: &json::JsonValue) -> String {
+    match value.as_str() {
+        Some(s) => s.to_string(),
+        None => value.dump(),
+    }
+}
+
+// Like `json_scalar`, no key takes nested arrays or tables so those end up empty
+fn toml_scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(d) => d.to_string(),
+        toml::Value::Array(_) | toml::Value::Table(_) => String::new(),
+    }
+}
+
+// One-based line and column of byte offset `offset`, as editors show them
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
 }
 
-impl SRUNClient {
-    pub fn from_app_config(config: &AppConfig) -> SRUNClient {
-        let mut client = SRUNClient {
-            server: config.server.clone().unwrap(),
-            credentials: None,
-            options: SRUNClientOptions {
-                redirect_host: config
+/// The configuration file used when none is given: `$XDG_CONFIG_HOME/srun-cli/config.toml`
+/// (`~/.config` if unset), then `/etc/srun-cli/config.toml`.
+pub fn default_config_path() -> Option<PathBuf> {
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    user_dir
+        .map(|dir| dir.join("srun-cli").join("config.toml"))
+        .into_iter()
+        .chain([PathBuf::from("/etc/srun-cli/config.toml")])
+        .find(|path| path.is_file())
+}
+
+// Password files and commands such as `pass show` may carry a trailing newline or more lines
+// An empty password would only fail later as rejected credentials, with no hint why
+fn first_line(content: &str, source: &str) -> Result<SecretString, String> {
+    match content.lines().next() {
+        Some(line) if !line.is_empty() => Ok(line.into()),
+        _ => Err(format!("Empty password from {source}")),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl AppConfig {
+    /// Finds the portal through the redirect the gateway injects into the probe request.
+    pub fn discover(&self) -> Result<PortalDiscovery, SRUNClientError> {
+        let options = SRUNClientOptions::default();
+        let probe_url = self.redirect_host.clone().unwrap_or(options.redirect_host);
+        discovery::discover_portal(self.transport().as_ref(), &probe_url)
+    }
+
+    /// Runs the connectivity probes, the redirect host serves as the only probe if none are set.
+    pub fn check(&self) -> ConnectivityReport {
+        let probes = if self.probes.is_empty() {
+            vec![Probe::HttpStatus {
+                url: self
                     .redirect_host
                     .clone()
-                    .unwrap_or(String::from("http://www.google.cn/generate_204")),
-                interface: config.interface.clone(),
-            },
+                    .unwrap_or(SRUNClientOptions::default().redirect_host),
+                status: 204,
+            }]
+        } else {
+            self.probes.clone()
         };
-        match config.username.clone() {
-            Some(username) => match config.password.clone() {
-                Some(password) => {
-                    client.credentials = Some(SRUNClientCredentials {
-                        username: username,
-                        password: password,
-                    })
-                }
-                None => {}
-            },
-            None => {}
+        ConnectivityChecker::new(probes, self.quorum)
+            .with_connect_timeout(seconds(self.connect_timeout))
+            .check(self.transport().as_ref())
+    }
+
+    // The transport of requests made outside of a client, or of a client replaying a
+    // cassette, traced and recorded like a client's
+    fn transport(&self) -> Box<dyn Transport> {
+        let transport: Box<dyn Transport> = match &self.replayer {
+            Some(replayer) => Box::new(replayer.clone()),
+            None => Box::new(
+                ReqwestTransport::new(self.interface.clone())
+                    .with_timeouts(seconds(self.connect_timeout), seconds(self.timeout)),
+            ),
+        };
+        let transport: Box<dyn Transport> = match &self.trace_sink {
+            Some(sink) => Box::new(TraceTransport::new(transport, sink.clone())),
+            None => transport,
+        };
+        match &self.recorder {
+            Some(recorder) => Box::new(RecordingTransport::new(transport, recorder.clone())),
+            None => transport,
+        }
+    }
+
+    /// Builds the client, moving the password out of the configuration into its credentials.
+    pub fn build_client(&mut self) -> SRUNClient {
+        let mut options = SRUNClientOptions::default();
+        if let Some(redirect_host) = self.redirect_host.clone() {
+            options.redirect_host = redirect_host;
+        }
+        options.interface = self.interface.clone();
+        options.connect_timeout = seconds(self.connect_timeout);
+        options.timeout = seconds(self.timeout);
+        options.retry.retries = self.retries;
+        options.retry.backoff = seconds(self.retry_backoff).unwrap_or(Duration::ZERO);
+        options.retry.on_network_error = self.retry_on_network;
+        options.retry.on_server_error = self.retry_on_server_error;
+        options.probes = self.probes.clone();
+        options.quorum = self.quorum;
+        options.portal = self.portal.clone();
+        options.endpoints = self.endpoints.clone();
+        options.ac_id = self.ac_id.clone();
+        options.discovered_ac_id = self.discovered_ac_id.clone();
+        options.default_ac_id = self.default_ac_id.clone();
+        options.trace = self.trace_sink.clone();
+        options.record = self.recorder.clone();
+        if self.dual_stack {
+            let mut ipv6 = Ipv6Options::default();
+            ipv6.server = self.ipv6_server.clone();
+            ipv6.source = self.ipv6_source;
+            options.portal.double_stack = true;
+            options.ipv6 = Some(ipv6);
+        }
+
+        let credentials = match (&self.username, self.password.take()) {
+            (Some(username), Some(password)) => {
+                Some(SRUNClientCredentials::new(username, password))
+            }
+            // Logging out only sends the username, so no password is resolved for it
+            (Some(username), None) if self.command.as_deref() == Some("logout") => {
+                Some(SRUNClientCredentials::new(username, ""))
+            }
+            _ => None,
+        };
+
+        let server = self.server.as_deref().unwrap();
+        if self.replayer.is_some() {
+            if self.dual_stack {
+                log::warn!("Replaying a cassette covers the IPv4 session only");
+            }
+            return SRUNClient::with_transport(server, credentials, options, self.transport());
         }
-        client
+        SRUNClient::new(server, credentials, options)
+    }
+}
+
+// Zero, negative or otherwise unusable values disable the timeout
+fn seconds(value: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(value)
+        .ok()
+        .filter(|d| !d.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file or directory under the system's temporary directory, removed when dropped
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn file(name: &str, content: &str) -> TempPath {
+            let path = env::temp_dir().join(format!("srun-cli-{}-{name}", std::process::id()));
+            fs::write(&path, content).unwrap();
+            TempPath(path)
+        }
+
+        fn dir(name: &str) -> TempPath {
+            let path = env::temp_dir().join(format!("srun-cli-{}-{name}", std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            TempPath(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn resolve(config: &mut AppConfig, stdin: &str) -> Result<String, String> {
+        config.resolve_password_from(&mut stdin.as_bytes(), None, false)?;
+        Ok(config
+            .password
+            .as_ref()
+            .map(|p| p.expose().to_string())
+            .unwrap_or_default())
+    }
+
+    #[test]
+    fn password_sources_take_first_line() {
+        let mut config = AppConfig::new();
+        config.password_stdin = true;
+        assert_eq!(
+            resolve(&mut config, "from stdin\nrest\n").unwrap(),
+            "from stdin"
+        );
+
+        let file = TempPath::file("password-file", "from file\n");
+        let mut config = AppConfig::new();
+        config.password_file = Some(file.path());
+        assert_eq!(resolve(&mut config, "").unwrap(), "from file");
+
+        let mut config = AppConfig::new();
+        config.password_command = Some(String::from("printf 'from command\\nrest'"));
+        assert_eq!(resolve(&mut config, "").unwrap(), "from command");
+
+        let dir = TempPath::dir("credentials");
+        fs::write(dir.0.join("password"), "from credential\n").unwrap();
+        let mut config = AppConfig::new();
+        config
+            .resolve_password_from(&mut "".as_bytes(), Some(&dir.0), false)
+            .unwrap();
+        assert_eq!(config.password.unwrap().expose(), "from credential");
+    }
+
+    #[test]
+    fn password_sources_follow_precedence() {
+        let file = TempPath::file("precedence", "from file\n");
+
+        // stdin beats the password key
+        let mut config = AppConfig::new();
+        config.password_stdin = true;
+        config.password = Some("from key".into());
+        config.password_file = Some(file.path());
+        assert_eq!(resolve(&mut config, "from stdin\n").unwrap(), "from stdin");
+
+        // the key beats the file
+        let mut config = AppConfig::new();
+        config.password = Some("from key".into());
+        config.password_file = Some(file.path());
+        assert_eq!(resolve(&mut config, "").unwrap(), "from key");
+
+        // the file beats the command
+        let mut config = AppConfig::new();
+        config.password_file = Some(file.path());
+        config.password_command = Some(String::from("echo from command"));
+        assert_eq!(resolve(&mut config, "").unwrap(), "from file");
+
+        // nothing configured and no terminal to prompt on
+        let mut config = AppConfig::new();
+        assert_eq!(resolve(&mut config, "").unwrap(), "");
+        assert!(config.password.is_none());
+    }
+
+    #[test]
+    fn empty_password_is_rejected() {
+        let mut config = AppConfig::new();
+        config.password_stdin = true;
+        assert!(resolve(&mut config, "").is_err());
+        assert!(resolve(&mut config, "\nsecond line\n").is_err());
+
+        let file = TempPath::file("empty-password-file", "");
+        let mut config = AppConfig::new();
+        config.password_file = Some(file.path());
+        assert!(resolve(&mut config, "").is_err());
+
+        let mut config = AppConfig::new();
+        config.password_command = Some(String::from("true"));
+        assert!(resolve(&mut config, "").is_err());
+
+        let mut config = AppConfig::new();
+        config.set("password", "").unwrap();
+        assert!(resolve(&mut config, "").is_err());
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn set_parses_values_by_key() {
+        let mut config = AppConfig::new();
+        config.set("interval", "30").unwrap();
+        config.set("redirect", "yes").unwrap();
+        config.set("output", "JSON").unwrap();
+        config.set("portal.n", "100").unwrap();
+        config.set("retry_on", "5xx").unwrap();
+        config.set("verbose", "true").unwrap();
+
+        assert_eq!(config.interval, 30);
+        assert!(config.redirect);
+        assert!(matches!(config.output, OutputFormat::Json));
+        assert_eq!(config.portal.n, "100");
+        assert!(!config.retry_on_network && config.retry_on_server_error);
+        assert_eq!(config.verbose, 1);
+
+        assert!(config.set("interval", "soon").is_err());
+        assert!(config.set("redirect", "maybe").is_err());
+        assert_eq!(
+            config.set("intreface", "eth0"),
+            Err(String::from("Unknown key intreface"))
+        );
+    }
+
+    #[test]
+    fn list_values_are_taken_whole() {
+        let mut config = AppConfig::new();
+        config
+            .set_list(
+                "probes",
+                &[
+                    String::from("body:http://a.test/ x;y"),
+                    String::from("tcp:1.1.1.1:53"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(config.probes.len(), 2);
+        assert_eq!(config.probes[0].to_string(), "body:http://a.test/ x;y");
+
+        // The environment has no lists, so its value is split on semicolons
+        config
+            .set("probes", "http:http://a.test/; tcp:1.1.1.1:53")
+            .unwrap();
+        assert_eq!(config.probes.len(), 2);
+
+        config
+            .set_list("retry_on", &[String::from("network"), String::from("5xx")])
+            .unwrap();
+        assert!(config.retry_on_network && config.retry_on_server_error);
+        assert!(config
+            .set_list("interval", &[String::from("1"), String::from("2")])
+            .is_err());
+    }
+
+    #[test]
+    fn load_env_maps_variables_to_keys() {
+        let mut config = AppConfig::new();
+        let ignored = config
+            .load_env(env(&[
+                ("SRUN_INTERFACE", "eth1"),
+                ("SRUN_PORTAL_ENC_VER", "srun_bx2"),
+                ("SRUN_ENDPOINTS_INDEX", "/index.html"),
+                ("SRUN_PROFILE", "south"),
+                ("SRUN_FOO", "1"),
+                ("HOME", "/root"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.interface.as_deref(), Some("eth1"));
+        assert_eq!(config.portal.enc_ver, "srun_bx2");
+        assert_eq!(config.endpoints.index, "/index.html");
+        assert_eq!(ignored, vec![String::from("SRUN_FOO")]);
+
+        let err = AppConfig::new()
+            .load_env(env(&[("SRUN_INTERVAL", "soon")]))
+            .unwrap_err();
+        assert_eq!(err, "SRUN_INTERVAL: Invalid number for interval: soon");
+    }
+
+    #[test]
+    fn layers_override_in_order() {
+        let file = TempPath::file(
+            "layers.json",
+            r#"{
+                "interface": "file", "server": "http://file.test", "username": "file",
+                "timeout": 5, "redirect_host": "http://file.test/generate_204",
+                "profiles": {"south": {"interface": "profile", "server": "http://profile.test", "timeout": 7}}
+            }"#,
+        );
+        let mut config = AppConfig::new();
+        config.load_file(&file.path()).unwrap();
+        config.apply_profile(Some("south")).unwrap();
+        config
+            .load_env(env(&[
+                ("SRUN_INTERFACE", "env"),
+                ("SRUN_SERVER", "http://env.test"),
+            ]))
+            .unwrap();
+        config
+            .set_list("interface", &[String::from("cli")])
+            .unwrap();
+
+        assert_eq!(config.interface.as_deref(), Some("cli"));
+        assert_eq!(config.server.as_deref(), Some("http://env.test"));
+        assert_eq!(config.timeout, 7.0);
+        assert_eq!(config.username.as_deref(), Some("file"));
+        assert_eq!(
+            config.redirect_host.as_deref(),
+            Some("http://file.test/generate_204")
+        );
+        assert_eq!(config.interval, AppConfig::new().interval);
+    }
+
+    #[test]
+    fn load_file_reads_toml_and_json() {
+        let toml = TempPath::file(
+            "load.toml",
+            "server = \"http://toml.test\"\ninterval = 45\nprobes = [\"tcp:1.1.1.1:53\", \"dns:a;b.test\"]\n\n[portal]\nn = \"300\"\n",
+        );
+        let mut config = AppConfig::new();
+        config.load_file(&toml.path()).unwrap();
+        assert_eq!(config.server.as_deref(), Some("http://toml.test"));
+        assert_eq!(config.interval, 45);
+        assert_eq!(config.probes.len(), 2);
+        assert_eq!(config.portal.n, "300");
+
+        let json = TempPath::file(
+            "load.json",
+            r#"{"server": "http://json.test", "ac_id": 7, "redirect": true, "endpoints": {"index": "/index.html"}}"#,
+        );
+        let mut config = AppConfig::new();
+        config.load_file(&json.path()).unwrap();
+        assert_eq!(config.server.as_deref(), Some("http://json.test"));
+        assert_eq!(config.ac_id.as_deref(), Some("7"));
+        assert!(config.redirect);
+        assert_eq!(config.endpoints.index, "/index.html");
+    }
+
+    #[test]
+    fn load_file_errors_point_at_line_and_column() {
+        let toml = TempPath::file(
+            "broken.toml",
+            "server = \"http://a.test\"\ninterval = = 5\n",
+        );
+        let err = AppConfig::new().load_file(&toml.path()).unwrap_err();
+        assert!(err.starts_with(&format!("{}:2:12: ", toml.path())), "{err}");
+
+        let json = TempPath::file("broken.json", "{\n  \"server\": x\n}");
+        let err = AppConfig::new().load_file(&json.path()).unwrap_err();
+        assert_eq!(
+            err,
+            format!("{}:2:13: Unexpected character 'x'", json.path())
+        );
+
+        let unknown = TempPath::file("unknown.json", r#"{"intreface": "eth0"}"#);
+        let err = AppConfig::new().load_file(&unknown.path()).unwrap_err();
+        assert_eq!(err, format!("{}: Unknown key intreface", unknown.path()));
+    }
+
+    #[test]
+    fn apply_profile_picks_the_default_one() {
+        let file = TempPath::file(
+            "profiles.toml",
+            "default_profile = \"south\"\n\n[profiles.default]\ninterface = \"eth0\"\n\n[profiles.south]\ninterface = \"eth1\"\npassword = \"south-secret\"\n",
+        );
+        let mut config = AppConfig::new();
+        config.load_file(&file.path()).unwrap();
+        config.apply_profile(None).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("south"));
+        assert_eq!(config.interface.as_deref(), Some("eth1"));
+        assert_eq!(
+            config.password.as_ref().map(|p| p.expose().to_string()),
+            Some(String::from("south-secret"))
+        );
+        // Moved out rather than copied
+        assert!(config.profiles.iter().all(|p| p.password.is_none()));
+
+        let file = TempPath::file(
+            "profiles-default.toml",
+            "[profiles.default]\ninterface = \"eth0\"\n\n[profiles.south]\ninterface = \"eth1\"\n",
+        );
+        let mut config = AppConfig::new();
+        config.load_file(&file.path()).unwrap();
+        config.apply_profile(None).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("default"));
+        assert_eq!(config.interface.as_deref(), Some("eth0"));
+
+        let mut config = AppConfig::new();
+        config.apply_profile(None).unwrap();
+        assert_eq!(config.profile, None);
+    }
+
+    #[test]
+    fn apply_profile_rejects_unknown_profiles() {
+        let file = TempPath::file(
+            "profiles-unknown.toml",
+            "[profiles.south]\ninterface = \"eth1\"\n",
+        );
+        let mut config = AppConfig::new();
+        config.load_file(&file.path()).unwrap();
+        assert_eq!(
+            config.apply_profile(Some("north")),
+            Err(String::from("Unknown profile north"))
+        );
+        assert_eq!(config.interface, None);
     }
 }
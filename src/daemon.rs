@@ -0,0 +1,121 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use super::config::{AppConfig, OutputFormat};
+use super::exit_code;
+use super::login;
+use srun_cli::srun::client::{LoginOptions, SRUNClient};
+use srun_cli::srun::response::SRUNResponse;
+
+pub fn run(client: &SRUNClient, app_config: &AppConfig) {
+    let (shutdown_tx, shutdown_rx) = mpsc::channel();
+    if ctrlc::set_handler(move || {
+        let _ = shutdown_tx.send(());
+    })
+    .is_err()
+    {
+        log::error!("Failed to install signal handler");
+        std::process::exit(exit_code::CONFIG_ERROR);
+    }
+
+    let interval = Duration::from_secs(app_config.interval);
+    let max_backoff = Duration::from_secs(app_config.max_backoff.max(app_config.interval));
+    let mut failures: u32 = 0;
+    // IPv6 failures back off on their own, so they never hold up the IPv4 session
+    let mut ipv6_failures: u32 = 0;
+    let mut ipv6_next = Instant::now();
+    let mut options = LoginOptions::default();
+    options.redirect = app_config.redirect;
+    options.skip_ipv6 = true;
+
+    loop {
+        // DHCP may have handed the interface a new address since the last round
+        let status = client.refresh_transport().and_then(|_| client.query());
+
+        match status {
+            Ok(r) if r.error == "ok" => {
+                failures = 0;
+            }
+            Ok(_) => {
+                log::info!("Session is offline, logging in");
+                match login(client, &options) {
+                    Ok(r) => {
+                        failures = 0;
+                        if let Some(r) = r {
+                            report(app_config, r.response.as_ref());
+                        }
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        log::error!("Login failed: {}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                // The portal itself is unreachable, logging in would fail the same way
+                failures += 1;
+                log::error!("Status check failed: {}", e);
+            }
+        }
+
+        // The IPv6 session expires on its own schedule and backs off on its own failures
+        let ipv6_result = client
+            .ipv6()
+            .filter(|_| Instant::now() >= ipv6_next)
+            .map(|ipv6| {
+                ipv6.refresh_transport()
+                    .and_then(|_| ipv6.login_if_offline())
+            });
+        match ipv6_result {
+            Some(Ok(r)) => {
+                ipv6_failures = 0;
+                if let Some(r) = r {
+                    report(app_config, &r);
+                }
+            }
+            Some(Err(e)) => {
+                ipv6_failures += 1;
+                let delay = backoff(interval, max_backoff, ipv6_failures);
+                ipv6_next = Instant::now() + delay;
+                log::error!("IPv6 login failed: {}", e);
+                log::warn!(
+                    "{} consecutive IPv6 failures, next attempt in {} s",
+                    ipv6_failures,
+                    delay.as_secs()
+                );
+            }
+            None => {}
+        }
+
+        let delay = backoff(interval, max_backoff, failures);
+        if failures > 0 {
+            log::warn!(
+                "{} consecutive failures, next attempt in {} s",
+                failures,
+                delay.as_secs()
+            );
+        }
+
+        match shutdown_rx.recv_timeout(delay) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(_) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+fn report(app_config: &AppConfig, resp: &dyn SRUNResponse) {
+    match app_config.output {
+        OutputFormat::Plain => print!("{}", resp),
+        OutputFormat::Json => println!("{}", resp.to_json()),
+    }
+}
+
+fn backoff(interval: Duration, max_backoff: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+    // Double the wait after every consecutive failure so an unhealthy portal is not hammered
+    interval
+        .saturating_mul(1 << (failures - 1).min(16))
+        .min(max_backoff)
+}
@@ -0,0 +1,102 @@
+//! Process exit codes, documented in the README.
+
+use srun_cli::srun::error::SRUNClientError;
+use srun_cli::srun::reason::ReasonKind;
+use std::error::Error;
+
+pub const SUCCESS: i32 = 0;
+/// `query` found no session, `check` found the network unreachable.
+pub const OFFLINE: i32 = 1;
+/// Same as clap's exit code for invalid arguments.
+pub const USAGE_ERROR: i32 = 2;
+pub const ALREADY_ONLINE: i32 = 3;
+pub const BAD_CREDENTIALS: i32 = 4;
+pub const PORTAL_ERROR: i32 = 5;
+pub const NETWORK_UNREACHABLE: i32 = 6;
+pub const CONFIG_ERROR: i32 = 7;
+
+pub fn for_error(err: &(dyn Error + 'static)) -> i32 {
+    let err = match err.downcast_ref::<SRUNClientError>() {
+        Some(err) => err,
+        None => return PORTAL_ERROR,
+    };
+    match err {
+        SRUNClientError::Transport(_) | SRUNClientError::Interface { .. } => NETWORK_UNREACHABLE,
+        // Same code as main's own check for a missing username or password
+        SRUNClientError::MissingCredentials => USAGE_ERROR,
+        SRUNClientError::InvalidUrl { .. } | SRUNClientError::PortalNotFound { .. } => CONFIG_ERROR,
+        SRUNClientError::Portal { .. } => match err.reason().map(|r| r.kind) {
+            Some(ReasonKind::AlreadyOnline) => ALREADY_ONLINE,
+            Some(ReasonKind::BadCredentials | ReasonKind::UnknownUser) => BAD_CREDENTIALS,
+            _ => PORTAL_ERROR,
+        },
+        _ => PORTAL_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portal(ecode: Option<&str>, error: &str) -> SRUNClientError {
+        SRUNClientError::Portal {
+            ecode: ecode.map(String::from),
+            error: error.to_string(),
+            error_msg: None,
+        }
+    }
+
+    #[test]
+    fn portal_errors_map_by_reason() {
+        assert_eq!(
+            for_error(&portal(Some("E2901"), "login_error")),
+            BAD_CREDENTIALS
+        );
+        assert_eq!(
+            for_error(&portal(Some("E2531"), "login_error")),
+            BAD_CREDENTIALS
+        );
+        assert_eq!(
+            for_error(&portal(None, "ip_already_online_error")),
+            ALREADY_ONLINE
+        );
+        assert_eq!(
+            for_error(&portal(Some("E2620"), "login_error")),
+            PORTAL_ERROR
+        );
+        assert_eq!(for_error(&portal(None, "mystery_error")), PORTAL_ERROR);
+    }
+
+    #[test]
+    fn client_errors_map_by_kind() {
+        assert_eq!(
+            for_error(&SRUNClientError::Transport("connection refused".into())),
+            NETWORK_UNREACHABLE
+        );
+        assert_eq!(
+            for_error(&SRUNClientError::Interface {
+                interface: String::from("eth0"),
+                message: String::from("not found"),
+            }),
+            NETWORK_UNREACHABLE
+        );
+        assert_eq!(for_error(&SRUNClientError::MissingCredentials), USAGE_ERROR);
+        assert_eq!(
+            for_error(&SRUNClientError::InvalidUrl {
+                url: String::from("not a url"),
+                message: String::from("relative URL without a base"),
+            }),
+            CONFIG_ERROR
+        );
+        assert_eq!(
+            for_error(&SRUNClientError::Http { status: 502 }),
+            PORTAL_ERROR
+        );
+    }
+
+    #[test]
+    fn other_errors_are_portal_errors() {
+        let err = std::io::Error::other("unexpected");
+        assert_eq!(for_error(&err), PORTAL_ERROR);
+    }
+}
@@ -0,0 +1,38 @@
+//! Client library for the SRUN campus network portal.
+//!
+//! ```no_run
+//! use srun_cli::{LoginOptions, LoginOutcome, SRUNClient, SRUNClientCredentials, SRUNClientOptions};
+//!
+//! let client = SRUNClient::new(
+//!     "http://10.0.0.1",
+//!     Some(SRUNClientCredentials::new("user", "password")),
+//!     SRUNClientOptions::default(),
+//! );
+//! if let LoginOutcome::Completed { response, .. } = client.full_login(&LoginOptions::default())? {
+//!     println!("{}", response);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod srun;
+
+pub use srun::cassette::{
+    Cassette, CassetteRecorder, Exchange, RecordingTransport, ReplayTransport,
+};
+pub use srun::client::{
+    EndpointPaths, Ipv6Options, LoginOptions, LoginOutcome, LoginPlan, LogoutOutcome,
+    PortalProfile, RetryPolicy, SRUNClient, SRUNClientCredentials, SRUNClientOptions,
+};
+pub use srun::discovery::AcIdSource;
+pub use srun::error::SRUNClientError;
+pub use srun::reason::{Reason, ReasonKind};
+pub use srun::response::{
+    ECode, SRUNChallengeResponse, SRUNDualStackResponse, SRUNLoginResponse, SRUNLogoutResponse,
+    SRUNQueryResponse, SRUNResponse,
+};
+pub use srun::secret::SecretString;
+pub use srun::srbx1::SRBX1;
+pub use srun::trace::{TraceSink, TraceTransport};
+pub use srun::transport::{
+    HttpRequest, HttpResponse, MemoryTransport, ReqwestTransport, Transport,
+};
@@ -0,0 +1,178 @@
+//! Diagnostics, kept apart from the command's output on stdout.
+
+#[cfg(unix)]
+use log::Level;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::str::FromStr;
+
+#[cfg(unix)]
+const IDENTIFIER: &str = "srun-cli";
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Values accepted by `--log-target`, syslog and journald are reached through Unix sockets.
+#[cfg(unix)]
+pub const TARGETS: &[&str] = &["stderr", "syslog", "journald"];
+#[cfg(not(unix))]
+pub const TARGETS: &[&str] = &["stderr"];
+
+/// Where log records go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    #[cfg(unix)]
+    Syslog,
+    #[cfg(unix)]
+    Journald,
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stderr" => Ok(LogTarget::Stderr),
+            #[cfg(unix)]
+            "syslog" => Ok(LogTarget::Syslog),
+            #[cfg(unix)]
+            "journald" => Ok(LogTarget::Journald),
+            #[cfg(not(unix))]
+            "syslog" | "journald" => Err(format!("Log target {s} is only available on Unix")),
+            _ => Err(format!("Unknown log target {s}")),
+        }
+    }
+}
+
+/// Maximum level for `-v` given `verbose` times, `--quiet` leaves only errors.
+pub fn level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+enum Sink {
+    Stderr,
+    #[cfg(unix)]
+    Syslog(UnixDatagram),
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+}
+
+struct Logger {
+    level: LevelFilter,
+    sink: Sink,
+}
+
+/// Installs the logger, falling back to stderr if the syslog or journald socket is missing.
+pub fn init(level: LevelFilter, target: LogTarget) -> Result<(), String> {
+    #[cfg(unix)]
+    let connect = |path: &str| {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok::<_, std::io::Error>(socket)
+    };
+    let (sink, fallback): (Sink, Option<String>) = match target {
+        LogTarget::Stderr => (Sink::Stderr, None),
+        #[cfg(unix)]
+        LogTarget::Syslog => match connect(SYSLOG_SOCKET) {
+            Ok(socket) => (Sink::Syslog(socket), None),
+            Err(e) => (Sink::Stderr, Some(format!("{SYSLOG_SOCKET}: {e}"))),
+        },
+        #[cfg(unix)]
+        LogTarget::Journald => match connect(JOURNALD_SOCKET) {
+            Ok(socket) => (Sink::Journald(socket), None),
+            Err(e) => (Sink::Stderr, Some(format!("{JOURNALD_SOCKET}: {e}"))),
+        },
+    };
+    log::set_boxed_logger(Box::new(Logger { level, sink })).map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+    if let Some(reason) = fallback {
+        log::warn!("Logging to stderr, {reason}");
+    }
+    Ok(())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Dependencies such as reqwest log plenty at debug level, only show them when tracing
+        metadata.level() <= self.level
+            && (self.level == LevelFilter::Trace || metadata.target().starts_with("srun_cli"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // A logger has nowhere to report its own failures, so they are dropped
+        match &self.sink {
+            Sink::Stderr => {
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "{}: {}",
+                    record.level().as_str().to_lowercase(),
+                    record.args()
+                );
+            }
+            #[cfg(unix)]
+            Sink::Syslog(socket) => {
+                // Facility daemon, RFC 3164 framing as understood by /dev/log
+                let priority = 3 * 8 + severity(record.level());
+                let message = format!(
+                    "<{priority}>{IDENTIFIER}[{}]: {}",
+                    std::process::id(),
+                    record.args()
+                );
+                let _ = socket.send(message.as_bytes());
+            }
+            #[cfg(unix)]
+            Sink::Journald(socket) => {
+                let mut datagram = Vec::new();
+                journald_field(
+                    &mut datagram,
+                    "PRIORITY",
+                    &severity(record.level()).to_string(),
+                );
+                journald_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+                journald_field(&mut datagram, "TARGET", record.target());
+                journald_field(&mut datagram, "MESSAGE", &record.args().to_string());
+                let _ = socket.send(&datagram);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(unix)]
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// Journald's native protocol: `KEY=value` lines, or a length-prefixed value if it has newlines
+#[cfg(unix)]
+fn journald_field(datagram: &mut Vec<u8>, key: &str, value: &str) {
+    datagram.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
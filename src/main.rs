@@ -1,131 +1,293 @@
 mod cli;
 mod config;
-mod srun;
+mod daemon;
+mod exit_code;
+mod logging;
 
 use std::error::Error;
 
 use cli::process_cli;
-use config::OutputFormat;
+use config::{AppConfig, OutputFormat};
 use json::object;
-use srun::client::SRUNClient;
-use srun::response::SRUNResponse;
+use srun_cli::srun::client::{LoginOptions, LoginOutcome, SRUNClient};
+use srun_cli::srun::error::SRUNClientError;
+use srun_cli::srun::response::{SRUNDualStackResponse, SRUNResponse};
 
 fn main() {
-    let app_config = process_cli();
-
-    let client = SRUNClient::from_app_config(&app_config);
+    let mut app_config = process_cli();
+    if let Err(e) = logging::init(
+        logging::level(app_config.verbose, app_config.quiet),
+        app_config.log_target,
+    ) {
+        eprintln!("Failed to set up logging: {}", e);
+    }
 
     let mut resp: Option<Box<dyn SRUNResponse>> = None;
     let mut err: Option<Box<dyn Error>> = None;
+    // The IPv6 session failing does not undo the IPv4 request, it only changes the exit code
+    let mut ipv6_err: Option<SRUNClientError> = None;
 
-    match app_config.command.unwrap().as_str() {
-        "query" => {
-            match query(&client) {
-                Ok(r) => {
-                    resp = Some(r);
-                }
-                Err(e) => {
-                    err = Some(e);
-                }
-            };
+    let command = app_config.command.clone().unwrap();
+    if command == "profiles" {
+        list_profiles(&app_config);
+        return;
+    }
+    if command == "check" {
+        let report = app_config.check();
+        match app_config.output {
+            OutputFormat::Plain => print!("{}", report),
+            OutputFormat::Json => println!("{}", report.to_json()),
         }
-        "login" => {
-            // check whether username and password are provided
-            if app_config.username.is_none() || app_config.password.is_none() {
-                println!("Username and password must be provided");
-                std::process::exit(1);
-            }
+        std::process::exit(if report.online {
+            exit_code::SUCCESS
+        } else {
+            exit_code::OFFLINE
+        });
+    }
 
-            match login(
-                &client,
-                app_config.redirect,
-                matches!(app_config.output, OutputFormat::Plain),
-                app_config.quick_abort,
-            ) {
-                Ok(r) => {
-                    resp = Some(r);
+    if command == "discover" || app_config.server.is_none() {
+        match app_config.discover() {
+            Ok(d) => {
+                log::info!("Discovered portal {} from {}", d.server, d.location);
+                app_config.server = Some(d.server.clone());
+                app_config.discovered_ac_id = d.ac_id.clone();
+                if command == "discover" {
+                    resp = Some(Box::new(d));
                 }
-                Err(e) => {
-                    err = Some(e);
-                }
-            };
-        }
-        "logout" => {
-            if app_config.username.is_none() {
-                println!("Username must be provided");
-                std::process::exit(1);
             }
+            Err(e) => {
+                err = Some(Box::new(e));
+            }
+        }
+    }
 
-            match logout(&client) {
-                Ok(r) => {
-                    resp = Some(r);
+    if err.is_none() && (command == "login" || command == "daemon") {
+        if let Err(e) = app_config.resolve_password() {
+            exit_with_error(&app_config, e.into(), exit_code::CONFIG_ERROR);
+        }
+        // Checked here, building the client moves the password out of the configuration
+        if app_config.username.is_none() || app_config.password.is_none() {
+            exit_with_error(
+                &app_config,
+                "Username and password must be provided".into(),
+                exit_code::USAGE_ERROR,
+            );
+        }
+    }
+
+    if err.is_none() {
+        let client = app_config.build_client();
+
+        match command.as_str() {
+            "query" => {
+                match query(&client) {
+                    Ok(r) => {
+                        resp = Some(r.response);
+                        ipv6_err = r.ipv6_error;
+                    }
+                    Err(e) => {
+                        err = Some(e);
+                    }
+                };
+            }
+            "login" => {
+                if app_config.dry_run {
+                    match client.prepare_login() {
+                        Ok(plan) => resp = Some(Box::new(plan)),
+                        Err(e) => err = Some(Box::new(e)),
+                    }
+                } else {
+                    let mut options = LoginOptions::default();
+                    options.redirect = app_config.redirect;
+                    options.abort_if_online = app_config.quick_abort;
+                    match login(&client, &options) {
+                        Ok(Some(r)) => {
+                            resp = Some(r.response);
+                            ipv6_err = r.ipv6_error;
+                        }
+                        Ok(None) => {
+                            let message = "Portal testing indicates you're online already.";
+                            match app_config.output {
+                                OutputFormat::Plain => println!("{}", message),
+                                OutputFormat::Json => {
+                                    let obj = object! {
+                                    "status" => "already_online", "message" => message};
+                                    print!("{}", obj.dump());
+                                }
+                            }
+                            std::process::exit(exit_code::ALREADY_ONLINE);
+                        }
+                        Err(e) => {
+                            err = Some(e);
+                        }
+                    };
                 }
-                Err(e) => {
-                    err = Some(e);
+            }
+            "logout" => {
+                if app_config.username.is_none() {
+                    exit_with_error(
+                        &app_config,
+                        "Username must be provided".into(),
+                        exit_code::USAGE_ERROR,
+                    );
                 }
-            };
+
+                match logout(&client) {
+                    Ok(r) => {
+                        resp = Some(r.response);
+                        ipv6_err = r.ipv6_error;
+                    }
+                    Err(e) => {
+                        err = Some(e);
+                    }
+                };
+            }
+            "daemon" => {
+                daemon::run(&client, &app_config);
+            }
+            _ => {}
         }
-        _ => {}
     }
 
+    if let Some(err) = err {
+        let code = exit_code::for_error(err.as_ref());
+        exit_with_error(&app_config, err, code);
+    }
+    if let Some(resp) = resp {
+        match app_config.output {
+            OutputFormat::Plain => print!("{}", resp),
+            OutputFormat::Json => print!("{}", resp.to_json()),
+        }
+        // login and logout turn portal failures into errors, only a query can end up here
+        if !resp.is_ok() {
+            std::process::exit(exit_code::OFFLINE);
+        }
+    }
+    if let Some(e) = ipv6_err {
+        log::error!("IPv6 login failed: {}", e);
+        std::process::exit(exit_code::for_error(&e));
+    }
+}
+
+/// Reports `err` and exits with `code`. Plain errors go to stderr, JSON ones to stdout as the
+/// output of the command.
+fn exit_with_error(app_config: &AppConfig, err: Box<dyn Error>, code: i32) -> ! {
+    match app_config.output {
+        OutputFormat::Plain => eprintln!("{}", err),
+        OutputFormat::Json => {
+            let mut error_obj = object! {
+            "error" => err.to_string()};
+            if let Some(reason) = err
+                .downcast_ref::<SRUNClientError>()
+                .and_then(SRUNClientError::reason)
+            {
+                error_obj["reason"] = reason.into();
+            }
+            print!("{}", error_obj.dump());
+        }
+    }
+    std::process::exit(code);
+}
+
+fn list_profiles(app_config: &AppConfig) {
+    // The profile this invocation applied, i.e. --profile or the default one
+    let active = app_config.profile.as_deref();
     match app_config.output {
         OutputFormat::Plain => {
-            if err.is_some() {
-                println!("{}", err.unwrap());
-                std::process::exit(1);
+            if app_config.profiles.is_empty() {
+                println!("No profiles configured");
             }
-            if resp.is_some() {
-                print!("{}", resp.unwrap().to_string());
+            for profile in &app_config.profiles {
+                let marker = if active == Some(profile.name.as_str()) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!(
+                    "{} {}\tserver: {}\tusername: {}\tinterface: {}",
+                    marker,
+                    profile.name,
+                    profile.get("server").unwrap_or("-"),
+                    profile.get("username").unwrap_or("-"),
+                    profile.get("interface").unwrap_or("-"),
+                );
             }
         }
         OutputFormat::Json => {
-            if err.is_some() {
-                let error_obj = object! {
-                "error" => err.unwrap().to_string()};
-                print!("{}", error_obj.dump());
-                std::process::exit(1);
-            }
-            if resp.is_some() {
-                print!("{}", resp.unwrap().to_json());
+            let mut list = json::JsonValue::new_array();
+            for profile in &app_config.profiles {
+                list.push(object! {
+                    "name" => profile.name.as_str(),
+                    "active" => active == Some(profile.name.as_str()),
+                    "server" => profile.get("server"),
+                    "username" => profile.get("username"),
+                    "interface" => profile.get("interface"),
+                })
+                .unwrap();
             }
+            print!("{}", list.dump());
         }
     }
 }
 
-fn query(client: &SRUNClient) -> Result<Box<dyn SRUNResponse>, Box<dyn Error>> {
+/// A request that went through for IPv4, the IPv6 session may still have failed.
+struct DualStackResult {
+    response: Box<dyn SRUNResponse>,
+    ipv6_error: Option<SRUNClientError>,
+}
+
+impl DualStackResult {
+    fn new<T: SRUNResponse + 'static>(
+        client: &SRUNClient,
+        ipv4: T,
+        ipv6: Option<Result<T, SRUNClientError>>,
+    ) -> DualStackResult {
+        let (ipv6, ipv6_error) = match ipv6 {
+            Some(Ok(r)) => (Some(r), None),
+            Some(Err(e)) => (None, Some(e)),
+            None => (None, None),
+        };
+        let response: Box<dyn SRUNResponse> = match client.ipv6() {
+            Some(_) => Box::new(SRUNDualStackResponse { ipv4, ipv6 }),
+            None => Box::new(ipv4),
+        };
+        DualStackResult {
+            response,
+            ipv6_error,
+        }
+    }
+}
+
+fn query(client: &SRUNClient) -> Result<DualStackResult, Box<dyn Error>> {
     let r = client.query()?;
-    Ok(Box::new(r))
+    let ipv6 = client.ipv6().map(SRUNClient::query);
+    Ok(DualStackResult::new(client, r, ipv6))
 }
 
 fn login(
     client: &SRUNClient,
-    redirect: bool,
-    output_warning: bool,
-    abort_if_online: bool,
-) -> Result<Box<dyn SRUNResponse>, Box<dyn Error>> {
-    // In some rare cases, http hijacking (redirection) must be triggered once to kick off BAS response
-    if redirect {
-        let status = client.access_redirect_host()?;
-
-        if status && output_warning {
-            println!("Portal testing returned 204 code, which indicates you're online.");
-        }
-
-        if status && abort_if_online {
-            std::process::exit(0);
+    options: &LoginOptions,
+) -> Result<Option<DualStackResult>, Box<dyn Error>> {
+    match client.full_login(options)? {
+        LoginOutcome::AlreadyOnline => Ok(None),
+        LoginOutcome::Completed {
+            probe_online,
+            response,
+            ac_id,
+            ac_id_source,
+            ipv6_response,
+        } => {
+            log::info!("Using ac_id {} from {}", ac_id, ac_id_source);
+            if probe_online {
+                log::warn!("Portal testing returned 204 code, which indicates you're online.");
+            }
+            let ipv6_response = ipv6_response.map(|r| r.map(|r| *r));
+            Ok(Some(DualStackResult::new(client, *response, ipv6_response)))
         }
     }
-
-    let r = client.query()?;
-    let cr = client.get_challenge(&r.online_ip)?;
-    let ac_id = client.get_ac_id()?;
-    let lr = client.login(&cr.challenge, &r.online_ip, &ac_id)?;
-    Ok(Box::new(lr))
 }
 
-fn logout(client: &SRUNClient) -> Result<Box<dyn SRUNResponse>, Box<dyn Error>> {
-    let r = client.query()?;
-    let ac_id = client.get_ac_id()?;
-    let lr = client.logout(&r.online_ip, &ac_id)?;
-    Ok(Box::new(lr))
+fn logout(client: &SRUNClient) -> Result<DualStackResult, Box<dyn Error>> {
+    let r = client.full_logout()?;
+    Ok(DualStackResult::new(client, r.response, r.ipv6_response))
 }
@@ -1,4 +1,24 @@
+//! SRUN portal protocol implementation.
+
+/// Recording portal sessions and replaying them.
+pub mod cassette;
+/// The portal client and its options.
 pub mod client;
+/// Checks whether the network beyond the gateway is reachable.
+pub mod connectivity;
+/// Helpers to find the portal and its `ac_id` from redirects.
+pub mod discovery;
+/// Errors reported by the client.
 pub mod error;
+/// Explanations for the error codes and messages of SRUN portals.
+pub mod reason;
+/// Decoded portal responses.
 pub mod response;
+/// A string type for passwords that keeps them out of logs.
+pub mod secret;
+/// The `{SRBX1}` encoder used by the login request.
 pub mod srbx1;
+/// A transport wrapper writing a wire trace of every request.
+pub mod trace;
+/// HTTP transports the client sends its requests through.
+pub mod transport;
@@ -0,0 +1,252 @@
+use super::error::SRUNClientError;
+use super::trace::SECRET_PARAMS;
+use super::transport::{HttpRequest, HttpResponse, Transport};
+use json::{object, JsonValue};
+use reqwest::Url;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const VERSION: u32 = 1;
+const REDACTED: &str = "[REDACTED]";
+
+/// One request and what it got back, a response or the transport error's message.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub request: HttpRequest,
+    pub response: Result<HttpResponse, String>,
+}
+
+/// A recorded portal session, stored as JSON.
+///
+/// ```json
+/// {"version": 1, "exchanges": [{
+///     "request": {"url": "http://10.0.0.1/cgi-bin/rad_user_info", "query": [["callback", "cb"]]},
+///     "response": {"status": 200, "headers": [], "body": "cb({...})"}
+/// }]}
+/// ```
+///
+/// A failed request has `"error": "message"` in place of `response`.
+#[derive(Debug, Clone, Default)]
+pub struct Cassette {
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Cassette> {
+        let content = fs::read_to_string(path)?;
+        Cassette::parse(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(content: &str) -> Result<Cassette, String> {
+        let doc = json::parse(content).map_err(|e| e.to_string())?;
+        match doc["version"].as_u32() {
+            Some(VERSION) => {}
+            _ => return Err(format!("Unsupported cassette version {}", doc["version"])),
+        }
+        let exchanges = doc["exchanges"]
+            .members()
+            .enumerate()
+            .map(|(i, exchange)| {
+                parse_exchange(exchange).map_err(|e| format!("Exchange {}: {e}", i + 1))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Cassette { exchanges })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json().pretty(2))
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let exchanges: Vec<JsonValue> = self.exchanges.iter().map(exchange_json).collect();
+        object! {
+            version: VERSION,
+            exchanges: exchanges,
+        }
+    }
+}
+
+fn exchange_json(exchange: &Exchange) -> JsonValue {
+    let pairs = |pairs: &[(String, String)]| -> Vec<JsonValue> {
+        pairs
+            .iter()
+            .map(|(k, v)| JsonValue::from(vec![k.as_str(), v.as_str()]))
+            .collect()
+    };
+    let mut obj = object! {
+        request: {
+            url: exchange.request.url.as_str(),
+            query: pairs(&exchange.request.query),
+        },
+    };
+    match &exchange.response {
+        Ok(resp) => {
+            obj["response"] = object! {
+                status: resp.status,
+                headers: pairs(&resp.headers),
+                body: resp.body.as_str(),
+            }
+        }
+        Err(e) => obj["error"] = e.as_str().into(),
+    }
+    obj
+}
+
+fn parse_exchange(exchange: &JsonValue) -> Result<Exchange, String> {
+    let pairs = |value: &JsonValue| -> Result<Vec<(String, String)>, String> {
+        value
+            .members()
+            .map(|pair| match (pair[0].as_str(), pair[1].as_str()) {
+                (Some(k), Some(v)) => Ok((k.to_string(), v.to_string())),
+                _ => Err(format!("Invalid pair {pair}")),
+            })
+            .collect()
+    };
+    let request = &exchange["request"];
+    let url = request["url"].as_str().ok_or("Missing request url")?;
+    let request = HttpRequest {
+        url: Url::parse(url).map_err(|e| format!("Invalid url {url}: {e}"))?,
+        query: pairs(&request["query"])?,
+    };
+    let response = match exchange["error"].as_str() {
+        Some(error) => Err(error.to_string()),
+        None => {
+            let response = &exchange["response"];
+            Ok(HttpResponse {
+                status: response["status"]
+                    .as_u16()
+                    .ok_or("Missing response status")?,
+                headers: pairs(&response["headers"])?,
+                body: response["body"].as_str().unwrap_or_default().to_string(),
+            })
+        }
+    };
+    Ok(Exchange { request, response })
+}
+
+/// Collects the exchanges of one or more [`RecordingTransport`]s and rewrites the cassette
+/// file after each one, so a session cut short is still recorded.
+#[derive(Debug, Clone)]
+pub struct CassetteRecorder {
+    path: PathBuf,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl CassetteRecorder {
+    /// Starts an empty cassette at `path`, replacing any file there.
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<CassetteRecorder> {
+        let recorder = CassetteRecorder {
+            path: path.into(),
+            cassette: Arc::default(),
+        };
+        Cassette::default().save(&recorder.path)?;
+        Ok(recorder)
+    }
+
+    /// The exchanges recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    fn record(&self, exchange: Exchange) {
+        let mut cassette = self.cassette.lock().unwrap();
+        cassette.exchanges.push(exchange);
+        // Recording must not break the session it records
+        if let Err(e) = cassette.save(&self.path) {
+            log::warn!("Failed to write cassette {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Passes requests on to another transport and records them, with the [`SECRET_PARAMS`]
+/// replaced by `[REDACTED]`.
+#[derive(Debug)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    recorder: CassetteRecorder,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T, recorder: CassetteRecorder) -> RecordingTransport<T> {
+        RecordingTransport { inner, recorder }
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        let result = self.inner.execute(request);
+        let mut scrubbed = request.clone();
+        for (key, value) in &mut scrubbed.query {
+            if SECRET_PARAMS.contains(&key.as_str()) {
+                *value = String::from(REDACTED);
+            }
+        }
+        self.recorder.record(Exchange {
+            request: scrubbed,
+            response: result
+                .as_ref()
+                .map(Clone::clone)
+                .map_err(ToString::to_string),
+        });
+        result
+    }
+
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        self.inner.refresh()
+    }
+}
+
+/// Serves the responses of a [`Cassette`] instead of sending requests.
+///
+/// Each request gets the first unused exchange for the same url path and, if present, the
+/// same `action` parameter, so login and logout at `srun_portal` are told apart. The server
+/// the session was recorded against does not matter.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: Mutex<Vec<Option<Exchange>>>,
+}
+
+impl ReplayTransport {
+    pub fn new(cassette: Cassette) -> ReplayTransport {
+        ReplayTransport {
+            exchanges: Mutex::new(cassette.exchanges.into_iter().map(Some).collect()),
+        }
+    }
+
+    /// Number of recorded exchanges no request has asked for yet.
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().iter().flatten().count()
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        let action = |request: &HttpRequest| {
+            request
+                .query
+                .iter()
+                .find(|(k, _)| k == "action")
+                .map(|(_, v)| v.clone())
+        };
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let exchange = exchanges
+            .iter_mut()
+            .find(|e| {
+                e.as_ref().is_some_and(|e| {
+                    e.request.url.path() == request.url.path()
+                        && action(&e.request) == action(request)
+                })
+            })
+            .and_then(Option::take);
+        match exchange {
+            Some(exchange) => exchange
+                .response
+                .map_err(|e| SRUNClientError::Transport(e.into())),
+            None => Err(SRUNClientError::Transport(
+                format!("no recorded exchange left for {}", request.url.path()).into(),
+            )),
+        }
+    }
+}
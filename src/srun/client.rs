@@ -1,263 +1,791 @@
+use super::cassette::{CassetteRecorder, RecordingTransport};
+use super::connectivity::{ConnectivityChecker, ConnectivityReport, Probe, Quorum};
+use super::discovery::{self, AcIdSource};
 use super::error::SRUNClientError;
 use super::response::SRUNChallengeResponse;
 use super::response::SRUNLoginResponse;
 use super::response::SRUNLogoutResponse;
 use super::response::SRUNQueryResponse;
+use super::response::SRUNResponse;
+use super::secret::SecretString;
 use super::srbx1::SRBX1;
+use super::trace::{TraceSink, TraceTransport};
+use super::transport::{HttpRequest, HttpResponse, ReqwestTransport, Transport};
 use hmac::{Hmac, Mac};
-use local_ip_address::list_afinet_netifas;
+use json::{object, JsonValue};
 use md5::Md5;
-use regex::Regex;
-use reqwest;
 use reqwest::Url;
 use sha1::{Digest, Sha1};
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::net::IpAddr;
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::thread;
+use std::time::{Duration, Instant};
 
 type HmacMd5 = Hmac<Md5>;
 
+/// Account used to authenticate against the portal.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct SRUNClientCredentials {
     pub username: String,
-    pub password: String,
+    pub password: SecretString,
 }
 
-#[derive(Debug)]
+impl SRUNClientCredentials {
+    pub fn new(username: &str, password: impl Into<SecretString>) -> SRUNClientCredentials {
+        SRUNClientCredentials {
+            username: username.to_string(),
+            password: password.into(),
+        }
+    }
+}
+
+/// Network level settings shared by every request a [`SRUNClient`] makes.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct SRUNClientOptions {
+    /// Probe URL which answers 204 when the network is reachable and gets hijacked otherwise.
     pub redirect_host: String,
+    /// Probes deciding whether we are online, an empty list checks `redirect_host` for a 204.
+    pub probes: Vec<Probe>,
+    /// How many of `probes` must pass.
+    pub quorum: Quorum,
+    /// Bind requests to an address of this interface instead of letting the OS choose.
     pub interface: Option<String>,
+    /// Upper bound for establishing a connection, `None` waits indefinitely.
+    pub connect_timeout: Option<Duration>,
+    /// Upper bound for a whole request including reading the response, `None` waits indefinitely.
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+    /// Deployment specific login parameters.
+    pub portal: PortalProfile,
+    /// Paths of the portal endpoints.
+    pub endpoints: EndpointPaths,
+    /// Use this `ac_id` instead of discovering it.
+    pub ac_id: Option<String>,
+    /// `ac_id` already found while discovering the portal, used unless `ac_id` is set.
+    pub discovered_ac_id: Option<String>,
+    /// `ac_id` to fall back to when discovery finds nothing.
+    pub default_ac_id: Option<String>,
+    /// Authenticate the IPv6 address too, in a session of its own.
+    pub ipv6: Option<Ipv6Options>,
+    /// Write every request and response to this sink.
+    pub trace: Option<TraceSink>,
+    /// Record every exchange into a cassette.
+    pub record: Option<CassetteRecorder>,
+}
+
+/// Where the IPv6 session is authenticated from.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Ipv6Options {
+    /// Portal url reachable over IPv6, defaults to the main server.
+    pub server: Option<String>,
+    /// Source address, defaults to the first global IPv6 address of the configured interface.
+    pub source: Option<Ipv6Addr>,
+}
+
+impl Default for SRUNClientOptions {
+    fn default() -> Self {
+        SRUNClientOptions {
+            redirect_host: String::from("http://www.google.cn/generate_204"),
+            probes: vec![],
+            quorum: Quorum::default(),
+            interface: None,
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            retry: RetryPolicy::default(),
+            portal: PortalProfile::default(),
+            endpoints: EndpointPaths::default(),
+            ac_id: None,
+            discovered_ac_id: None,
+            default_ac_id: None,
+            ipv6: None,
+            trace: None,
+            record: None,
+        }
+    }
+}
+
+/// Constants a SRUN deployment expects in the login request.
+///
+/// `n` and `login_type` are also part of `chksum`, and `enc_ver` is part of the `{SRBX1}` info
+/// field, so they must match what the portal's JavaScript sends.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PortalProfile {
+    pub enc_ver: String,
+    pub n: String,
+    /// Sent as the `type` parameter.
+    pub login_type: String,
+    pub os: String,
+    pub name: String,
+    /// JSONP callback name used for every portal request.
+    pub callback: String,
+    /// Tell the portal the client is dual-stack (`double_stack=1`).
+    pub double_stack: bool,
+}
+
+impl Default for PortalProfile {
+    fn default() -> Self {
+        PortalProfile {
+            enc_ver: String::from("srun_bx1"),
+            n: String::from("200"),
+            login_type: String::from("1"),
+            os: String::from("Windows 10"),
+            name: String::from("Windows"),
+            callback: String::from("FuckSRUNJsonP"),
+            double_stack: false,
+        }
+    }
+}
+
+/// Paths of the portal endpoints.
+///
+/// Relative paths are resolved against the server url, so a portal served under a prefix such
+/// as `https://gw.example.edu/srun/` works unchanged. A leading `/` makes a path relative to the
+/// host instead.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EndpointPaths {
+    pub rad_user_info: String,
+    pub get_challenge: String,
+    pub srun_portal: String,
+    /// Page redirecting to the login page, used to discover `ac_id`.
+    pub index: String,
+}
+
+impl Default for EndpointPaths {
+    fn default() -> Self {
+        EndpointPaths {
+            rad_user_info: String::from("cgi-bin/rad_user_info"),
+            get_challenge: String::from("cgi-bin/get_challenge"),
+            srun_portal: String::from("cgi-bin/srun_portal"),
+            index: String::from("index_1.html"),
+        }
+    }
+}
+
+/// Decides how often and when failed portal requests are repeated.
+///
+/// The login request itself is never repeated on its own, [`SRUNClient::full_login`] fetches a
+/// fresh challenge for every attempt instead.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RetryPolicy {
+    /// Attempts made after the first one failed.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each following one.
+    pub backoff: Duration,
+    /// Retry when the request could not be sent, e.g. connection refused or timed out.
+    pub on_network_error: bool,
+    /// Retry when the portal answered with a 5xx status.
+    pub on_server_error: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_secs(1),
+            on_network_error: true,
+            on_server_error: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a request failing with `err` is worth another attempt.
+    pub fn is_retryable(&self, err: &SRUNClientError) -> bool {
+        match err {
+            SRUNClientError::Transport(_) => self.on_network_error,
+            SRUNClientError::Http { status } => self.on_server_error && *status >= 500,
+            // The challenge timed out before we used it, a new one will do
+            SRUNClientError::Portal { error, .. } => error == "challenge_expire_error",
+            _ => false,
+        }
+    }
+}
+
+/// Controls the high level [`SRUNClient::full_login`] sequence.
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
+pub struct LoginOptions {
+    /// Access the redirect host first, some networks only start BAS after a hijacked request.
+    pub redirect: bool,
+    /// Skip logging in when [`SRUNClient::check_connectivity`] shows we are already online.
+    pub abort_if_online: bool,
+    /// Leave the IPv6 session alone, for callers that log it in on a schedule of their own.
+    pub skip_ipv6: bool,
+}
+
+/// Result of [`SRUNClient::full_login`].
+#[derive(Debug)]
+pub enum LoginOutcome {
+    /// The network was reachable and `abort_if_online` was set, no login was sent.
+    AlreadyOnline,
+    /// A login request was sent, `probe_online` tells whether the network was already
+    /// reachable beforehand.
+    Completed {
+        probe_online: bool,
+        response: Box<SRUNLoginResponse>,
+        /// The `ac_id` sent with the login and where it was found.
+        ac_id: String,
+        ac_id_source: AcIdSource,
+        /// Login of the IPv6 session, when [`SRUNClientOptions::ipv6`] is set and the IPv6
+        /// session was offline. A failure here leaves the IPv4 session logged in.
+        ipv6_response: Option<Result<Box<SRUNLoginResponse>, SRUNClientError>>,
+    },
 }
 
+/// Result of [`SRUNClient::full_logout`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct LogoutOutcome {
+    pub response: SRUNLogoutResponse,
+    /// Logout of the IPv6 session, when [`SRUNClientOptions::ipv6`] is set and the IPv6
+    /// session was online. A failure here leaves the IPv4 session logged out.
+    pub ipv6_response: Option<Result<SRUNLogoutResponse, SRUNClientError>>,
+}
+
+/// A login request worked out by [`SRUNClient::prepare_login`] but not sent.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LoginPlan {
+    pub ip: String,
+    pub ac_id: String,
+    pub ac_id_source: AcIdSource,
+    pub challenge: String,
+    pub request: HttpRequest,
+}
+
+impl LoginPlan {
+    /// The request url with its query, as it would be sent.
+    pub fn url(&self) -> Url {
+        let mut url = self.request.url.clone();
+        url.query_pairs_mut().extend_pairs(&self.request.query);
+        url
+    }
+}
+
+impl fmt::Display for LoginPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Dry run, the login request was not sent")?;
+        writeln!(f, "IP: {}", self.ip)?;
+        writeln!(f, "ac_id: {} (from {})", self.ac_id, self.ac_id_source)?;
+        writeln!(f, "Challenge: {}", self.challenge)?;
+        writeln!(f, "GET {}", self.request.url)?;
+        for (key, value) in &self.request.query {
+            writeln!(f, "  {key}={value}")?;
+        }
+        writeln!(f, "URL: {}", self.url())
+    }
+}
+
+impl SRUNResponse for LoginPlan {
+    fn to_json(&self) -> String {
+        let mut params = JsonValue::new_object();
+        for (key, value) in &self.request.query {
+            params[key.as_str()] = value.as_str().into();
+        }
+        let obj = object! {
+            ip: self.ip.clone(),
+            ac_id: self.ac_id.clone(),
+            ac_id_source: self.ac_id_source.to_string(),
+            challenge: self.challenge.clone(),
+            endpoint: self.request.url.as_str(),
+            params: params,
+            url: self.url().as_str(),
+        };
+        json::stringify(obj)
+    }
+
+    fn is_ok(&self) -> bool {
+        true
+    }
+}
+
+/// Client for the SRUN portal protocol.
+///
+/// The low level methods ([`query`](SRUNClient::query), [`get_challenge`](SRUNClient::get_challenge),
+/// [`get_ac_id`](SRUNClient::get_ac_id), [`login`](SRUNClient::login) and
+/// [`logout`](SRUNClient::logout)) map one to one to portal endpoints, while
+/// [`full_login`](SRUNClient::full_login) and [`full_logout`](SRUNClient::full_logout) chain
+/// them the way the web portal does.
 #[derive(Debug)]
 pub struct SRUNClient {
-    pub server: String,
-    pub credentials: Option<SRUNClientCredentials>,
-    pub options: SRUNClientOptions,
+    server: String,
+    credentials: Option<SRUNClientCredentials>,
+    options: SRUNClientOptions,
+    transport: Box<dyn Transport>,
+    ipv6: Option<Box<SRUNClient>>,
 }
 
 impl SRUNClient {
-    pub fn query(&self) -> Result<SRUNQueryResponse, Box<dyn std::error::Error>> {
-        let client = SRUNClient::get_client(self.options.interface.clone())?;
-        let mut u = Url::parse(&self.server)?;
-        u = u.join("/cgi-bin/rad_user_info")?;
-        let resp = client
-            .get(u.as_str())
-            .query(&[("callback", "FuckSRUNJsonP")])
-            .send()?;
-        if resp.status().as_u16() != 200 {
-            let status = resp.status().as_u16();
-            return Err(Box::new(SRUNClientError {
-                message: String::from(format!("Server responded with code {status}")),
-            }));
+    /// Creates a client for the portal at `server`, e.g. `http://10.0.0.1`.
+    pub fn new(
+        server: &str,
+        credentials: Option<SRUNClientCredentials>,
+        options: SRUNClientOptions,
+    ) -> SRUNClient {
+        let transport = ReqwestTransport::new(options.interface.clone())
+            .with_timeouts(options.connect_timeout, options.timeout);
+        let ipv6 = options.ipv6.clone().map(|ipv6| {
+            let transport = ReqwestTransport::ipv6(options.interface.clone(), ipv6.source)
+                .with_timeouts(options.connect_timeout, options.timeout);
+            let mut ipv6_options = options.clone();
+            ipv6_options.ipv6 = None;
+            SRUNClient::with_transport(
+                ipv6.server.as_deref().unwrap_or(server),
+                credentials.clone(),
+                ipv6_options,
+                wrap_transport(transport, &options),
+            )
+        });
+
+        let transport = wrap_transport(transport, &options);
+        let client = SRUNClient::with_transport(server, credentials, options, transport);
+        match ipv6 {
+            Some(ipv6) => client.with_ipv6_client(ipv6),
+            None => client,
         }
-        let content = resp.text()?;
+    }
+
+    /// Like [`new`](SRUNClient::new) but sends every request through `transport`.
+    pub fn with_transport(
+        server: &str,
+        credentials: Option<SRUNClientCredentials>,
+        options: SRUNClientOptions,
+        transport: Box<dyn Transport>,
+    ) -> SRUNClient {
+        SRUNClient {
+            server: server.to_string(),
+            credentials,
+            options,
+            transport,
+            ipv6: None,
+        }
+    }
+
+    /// Uses `client` to manage the IPv6 session alongside this one.
+    pub fn with_ipv6_client(mut self, client: SRUNClient) -> SRUNClient {
+        self.ipv6 = Some(Box::new(client));
+        self
+    }
+
+    /// The client managing the IPv6 session, if dual-stack authentication is enabled.
+    pub fn ipv6(&self) -> Option<&SRUNClient> {
+        self.ipv6.as_deref()
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
 
-        Ok(SRUNQueryResponse::from_string(SRUNClient::extract_jsonp(
-            content,
-        )?)?)
+    pub fn credentials(&self) -> Option<&SRUNClientCredentials> {
+        self.credentials.as_ref()
     }
 
-    pub fn access_redirect_host(&self) -> Result<bool, Box<dyn std::error::Error>> {
-        let client = SRUNClient::get_client(self.options.interface.clone())?;
-        let resp = client.get(self.options.redirect_host.clone()).send()?;
-        if resp.status().as_u16() == 204 {
-            return Ok(true);
+    pub fn options(&self) -> &SRUNClientOptions {
+        &self.options
+    }
+
+    /// Lets the transport pick up network changes, such as a new address on the configured
+    /// interface. Returns whether the transport was rebuilt. The IPv6 client, if any, is
+    /// refreshed on its own through [`ipv6`](SRUNClient::ipv6).
+    pub fn refresh_transport(&self) -> Result<bool, SRUNClientError> {
+        self.transport.refresh()
+    }
+
+    /// Queries the portal for the state of the current session, then fetches a challenge,
+    /// resolves `ac_id` and logs in. With an IPv6 client, its session is logged in afterwards
+    /// unless it is already online or `skip_ipv6` is set, and its result is reported apart from
+    /// the IPv4 login.
+    pub fn full_login(&self, options: &LoginOptions) -> Result<LoginOutcome, SRUNClientError> {
+        let mut probe_online = false;
+        if options.abort_if_online {
+            probe_online = self.check_connectivity().online;
+            if probe_online {
+                return Ok(LoginOutcome::AlreadyOnline);
+            }
         }
-        Ok(false)
+        // In some rare cases, http hijacking (redirection) must be triggered once to kick off BAS response.
+        // The default probe has already requested the redirect host.
+        if options.redirect && !(options.abort_if_online && self.options.probes.is_empty()) {
+            probe_online |= self.access_redirect_host()?;
+        }
+
+        let r = self.query()?;
+        let (ac_id, ac_id_source) = self.discover_ac_id()?;
+        // Every attempt needs a challenge of its own, a retried login would reuse an expired one
+        let response = self.with_retry(|| {
+            let cr = self.request_challenge(&r.online_ip)?;
+            let response = self.login(&cr.challenge, &r.online_ip, &ac_id)?;
+            if response.error != "ok" {
+                return Err(SRUNClientError::Portal {
+                    ecode: response.ecode.map(|c| c.to_string()),
+                    error: response.error,
+                    error_msg: response.error_msg,
+                });
+            }
+            Ok(response)
+        })?;
+        let ipv6_response = self
+            .ipv6
+            .as_ref()
+            .filter(|_| !options.skip_ipv6)
+            .and_then(|ipv6| ipv6.login_if_offline().map(|r| r.map(Box::new)).transpose());
+        Ok(LoginOutcome::Completed {
+            probe_online,
+            response: Box::new(response),
+            ac_id,
+            ac_id_source,
+            ipv6_response,
+        })
     }
 
-    pub fn get_challenge(
-        &self,
-        ip: &String,
-    ) -> Result<SRUNChallengeResponse, Box<dyn std::error::Error>> {
-        let client = SRUNClient::get_client(self.options.interface.clone())?;
-        let username = self.credentials.clone().unwrap().username;
-        let mut u = Url::parse(&self.server)?;
-        u = u.join("/cgi-bin/get_challenge")?;
-        let resp = client
-            .get(u.as_str())
-            .query(&[
-                ("callback", "FuckSRUNJsonP"),
-                ("username", &username),
-                ("ip", &ip),
-            ])
-            .send()?;
-        if resp.status().as_u16() != 200 {
-            let status = resp.status().as_u16();
-            return Err(Box::new(SRUNClientError {
-                message: String::from(format!("Server responded with code {status}")),
-            }));
+    /// Runs the steps of [`full_login`](SRUNClient::full_login) up to the login request and
+    /// returns that request instead of sending it. The challenge is fetched for real, so the
+    /// request expires like any other.
+    pub fn prepare_login(&self) -> Result<LoginPlan, SRUNClientError> {
+        let r = self.query()?;
+        let (ac_id, ac_id_source) = self.discover_ac_id()?;
+        let cr = self.get_challenge(&r.online_ip)?;
+        let request = self.login_request(&cr.challenge, &r.online_ip, &ac_id)?;
+        Ok(LoginPlan {
+            ip: r.online_ip,
+            ac_id,
+            ac_id_source,
+            challenge: cr.challenge,
+            request,
+        })
+    }
+
+    /// Logs the session in unless the portal already reports it online.
+    pub fn login_if_offline(&self) -> Result<Option<SRUNLoginResponse>, SRUNClientError> {
+        if self.query()?.error == "ok" {
+            return Ok(None);
+        }
+        match self.full_login(&LoginOptions::default())? {
+            LoginOutcome::Completed { response, .. } => Ok(Some(*response)),
+            LoginOutcome::AlreadyOnline => Ok(None),
         }
-        let content = resp.text()?;
-
-        Ok(SRUNChallengeResponse::from_string(
-            SRUNClient::extract_jsonp(content)?,
-        )?)
-    }
-
-    pub fn get_ac_id(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let client = SRUNClient::get_client(self.options.interface.clone())?;
-        let mut u = Url::parse(&self.server)?;
-        u = u.join("/index_1.html")?;
-        let resp = client.get(u.as_str()).send()?;
-        if resp.status().as_u16() != 302 {
-            let status = resp.status().as_u16();
-            return Err(Box::new(SRUNClientError {
-                message: String::from(format!("Server responded with code {status}")),
-            }));
+    }
+
+    /// Queries the portal for the current IP address, resolves `ac_id` and logs out. With an
+    /// IPv6 client, its session is logged out afterwards if it is online, and its result is
+    /// reported apart from the IPv4 logout.
+    pub fn full_logout(&self) -> Result<LogoutOutcome, SRUNClientError> {
+        let r = self.query()?;
+        let ac_id = self.get_ac_id()?;
+        let response = self.logout(&r.online_ip, &ac_id)?;
+        if response.error != "ok" {
+            return Err(SRUNClientError::Portal {
+                ecode: response.ecode.map(|c| c.to_string()),
+                error: response.error,
+                error_msg: response.error_msg,
+            });
         }
-        let redirect_address = resp.headers().get("Location").unwrap();
-        // extract ac_id=? via regex
-        let re = Regex::new(r"ac_id=(\d+)").unwrap();
-        let caps = re.captures(redirect_address.to_str().unwrap()).unwrap();
-        let ac_id = caps.get(1).unwrap().as_str();
-        Ok(ac_id.to_string())
+        let ipv6_response = self
+            .ipv6
+            .as_ref()
+            .and_then(|ipv6| ipv6.logout_if_online().transpose());
+        Ok(LogoutOutcome {
+            response,
+            ipv6_response,
+        })
     }
 
+    /// Logs the session out unless the portal already reports it offline.
+    pub fn logout_if_online(&self) -> Result<Option<SRUNLogoutResponse>, SRUNClientError> {
+        if self.query()?.error != "ok" {
+            return Ok(None);
+        }
+        Ok(Some(self.full_logout()?.response))
+    }
+
+    /// Fetches `rad_user_info`, which describes the session of the requesting address.
+    pub fn query(&self) -> Result<SRUNQueryResponse, SRUNClientError> {
+        self.with_retry(|| {
+            let content = self.get_jsonp(
+                &self.options.endpoints.rad_user_info,
+                &[("callback", &self.options.portal.callback)],
+            )?;
+
+            SRUNQueryResponse::from_string(content)
+        })
+    }
+
+    /// Accesses the redirect host, returns `true` if it answered 204 (i.e. we are online).
+    pub fn access_redirect_host(&self) -> Result<bool, SRUNClientError> {
+        Ok(self.probe()?.status == 204)
+    }
+
+    /// Runs the configured connectivity probes through this client's transport.
+    pub fn check_connectivity(&self) -> ConnectivityReport {
+        let probes = if self.options.probes.is_empty() {
+            vec![Probe::HttpStatus {
+                url: self.options.redirect_host.clone(),
+                status: 204,
+            }]
+        } else {
+            self.options.probes.clone()
+        };
+        ConnectivityChecker::new(probes, self.options.quorum)
+            .with_connect_timeout(self.options.connect_timeout)
+            .check(self.transport.as_ref())
+    }
+
+    fn probe(&self) -> Result<HttpResponse, SRUNClientError> {
+        let u =
+            Url::parse(&self.options.redirect_host).map_err(|e| SRUNClientError::InvalidUrl {
+                url: self.options.redirect_host.clone(),
+                message: e.to_string(),
+            })?;
+        self.execute(&HttpRequest::new(u, &[]))
+    }
+
+    /// Requests a login challenge for `ip`. Requires credentials.
+    pub fn get_challenge(&self, ip: &str) -> Result<SRUNChallengeResponse, SRUNClientError> {
+        self.with_retry(|| self.request_challenge(ip))
+    }
+
+    fn request_challenge(&self, ip: &str) -> Result<SRUNChallengeResponse, SRUNClientError> {
+        let username = &self.require_credentials()?.username;
+        let content = self.get_jsonp(
+            &self.options.endpoints.get_challenge,
+            &[
+                ("callback", &self.options.portal.callback),
+                ("username", username),
+                ("ip", ip),
+            ],
+        )?;
+
+        let cr = SRUNChallengeResponse::from_string(content)?;
+        if cr.error != "ok" {
+            return Err(SRUNClientError::Portal {
+                ecode: cr.ecode.map(|c| c.to_string()),
+                error: cr.error,
+                error_msg: cr.error_msg,
+            });
+        }
+        Ok(cr)
+    }
+
+    /// Resolves the `ac_id` of the portal, see [`discover_ac_id`](SRUNClient::discover_ac_id).
+    pub fn get_ac_id(&self) -> Result<String, SRUNClientError> {
+        self.discover_ac_id().map(|(ac_id, _)| ac_id)
+    }
+
+    /// Resolves the `ac_id` of the portal, trying in order: the configured value, the one found
+    /// by portal discovery, the redirect issued by `/index_1.html` (header, then HTML body), the
+    /// redirect the gateway injects into the captive-portal probe and the configured default.
+    pub fn discover_ac_id(&self) -> Result<(String, AcIdSource), SRUNClientError> {
+        if let Some(ac_id) = &self.options.ac_id {
+            return Ok((ac_id.clone(), AcIdSource::Configured));
+        }
+        if let Some(ac_id) = &self.options.discovered_ac_id {
+            return Ok((ac_id.clone(), AcIdSource::Discovered));
+        }
+
+        let mut location = None;
+        // Some deployments do not serve /index_1.html at all, any failure moves on to the probe
+        match self.with_retry(|| self.get(&self.options.endpoints.index, &[])) {
+            Ok(resp) if !(200..400).contains(&resp.status) => {
+                log::debug!("{} answered {}", self.options.endpoints.index, resp.status);
+            }
+            Ok(resp) => {
+                if let Some(header) = resp.header("Location") {
+                    if let Some(ac_id) = discovery::ac_id_from_url(header) {
+                        return Ok((ac_id, AcIdSource::LocationHeader));
+                    }
+                    location = Some(header.to_string());
+                }
+                if let Some(target) = discovery::html_redirect(&resp.body) {
+                    if let Some(ac_id) = discovery::ac_id_from_url(&target) {
+                        return Ok((ac_id, AcIdSource::HtmlRedirect));
+                    }
+                    location.get_or_insert(target);
+                }
+            }
+            Err(e) => log::debug!("{} failed: {}", self.options.endpoints.index, e),
+        }
+
+        // Only an offline client gets hijacked, an unreachable probe is not worth failing over
+        if let Ok(resp) = self.probe() {
+            if let Some(target) = discovery::redirect_target(&resp) {
+                if let Some(ac_id) = discovery::ac_id_from_url(&target) {
+                    return Ok((ac_id, AcIdSource::ProbeRedirect));
+                }
+            }
+        }
+
+        match &self.options.default_ac_id {
+            Some(ac_id) => Ok((ac_id.clone(), AcIdSource::Default)),
+            None => Err(SRUNClientError::AcIdNotFound { location }),
+        }
+    }
+
+    /// Sends the login request using a challenge from [`get_challenge`](SRUNClient::get_challenge).
+    /// Requires credentials.
     pub fn login(
         &self,
-        challenge: &String,
-        ip: &String,
-        ac_id: &String,
-    ) -> Result<SRUNLoginResponse, Box<dyn std::error::Error>> {
-        let client = SRUNClient::get_client(self.options.interface.clone())?;
-        let mut u = Url::parse(&self.server)?;
-        u = u.join("/cgi-bin/srun_portal")?;
-
-        let username = self.credentials.clone().unwrap().username;
-        let password = self.credentials.clone().unwrap().password;
-
-        let hmac = SRUNClient::calculate_password_hash(&password, challenge);
-        let srbx1 = SRBX1::new(&username, &password, &ip, &ac_id);
+        challenge: &str,
+        ip: &str,
+        ac_id: &str,
+    ) -> Result<SRUNLoginResponse, SRUNClientError> {
+        let request = self.login_request(challenge, ip, ac_id)?;
+        let content = self.send_jsonp(&request)?;
+
+        SRUNLoginResponse::from_string(content)
+    }
+
+    /// Builds the login request for `ip` without sending it, signing it with `challenge`.
+    /// Requires credentials.
+    pub fn login_request(
+        &self,
+        challenge: &str,
+        ip: &str,
+        ac_id: &str,
+    ) -> Result<HttpRequest, SRUNClientError> {
+        let credentials = self.require_credentials()?;
+        let username = &credentials.username;
+        let password = &credentials.password;
+        let portal = &self.options.portal;
+
+        let hmac = SRUNClient::calculate_password_hash(password.expose(), challenge);
+        let srbx1 = SRBX1::new(username, password, ip, ac_id).with_enc_ver(&portal.enc_ver);
         let auth_code = srbx1.calculate_auth_code(challenge);
 
-        let checksum =
-            SRUNClient::generate_checksum(&challenge, &username, &hmac, &ac_id, &ip, &auth_code);
+        let checksum = SRUNClient::generate_checksum(
+            challenge, username, &hmac, ac_id, ip, &auth_code, portal,
+        );
 
-        let resp = client
-            .get(u.as_str())
-            .query(&[
-                ("callback", "FuckSRUNJsonP"),
+        Ok(HttpRequest::new(
+            self.endpoint(&self.options.endpoints.srun_portal)?,
+            &[
+                ("callback", &portal.callback),
                 ("action", "login"),
-                ("username", &username),
+                ("username", username),
                 ("password", &format!("{}{}", "{MD5}", hmac)),
-                ("os", "Windows 10"),
-                ("name", "Windows"),
-                ("double_stack", "0"),
+                ("os", &portal.os),
+                ("name", &portal.name),
+                ("double_stack", if portal.double_stack { "1" } else { "0" }),
                 ("chksum", &checksum),
                 ("info", &auth_code),
-                ("ac_id", &ac_id),
-                ("ip", &ip),
-                ("n", "200"),
-                ("type", "1"),
-            ])
-            .send()?;
-        if resp.status().as_u16() != 200 {
-            let status = resp.status().as_u16();
-            return Err(Box::new(SRUNClientError {
-                message: String::from(format!("Server responded with code {status}")),
-            }));
-        }
-        let content = resp.text()?;
+                ("ac_id", ac_id),
+                ("ip", ip),
+                ("n", &portal.n),
+                ("type", &portal.login_type),
+            ],
+        ))
+    }
+
+    /// Sends the logout request for `ip`. Requires credentials.
+    pub fn logout(&self, ip: &str, ac_id: &str) -> Result<SRUNLogoutResponse, SRUNClientError> {
+        let username = &self.require_credentials()?.username;
+        self.with_retry(|| {
+            let content = self.get_jsonp(
+                &self.options.endpoints.srun_portal,
+                &[
+                    ("callback", &self.options.portal.callback),
+                    ("action", "logout"),
+                    ("username", username),
+                    ("ac_id", ac_id),
+                    ("ip", ip),
+                ],
+            )?;
 
-        Ok(SRUNLoginResponse::from_string(SRUNClient::extract_jsonp(
-            content,
-        )?)?)
+            SRUNLogoutResponse::from_string(content)
+        })
     }
 
-    pub fn logout(
+    fn with_retry<T>(
         &self,
-        ip: &String,
-        ac_id: &String,
-    ) -> Result<SRUNLogoutResponse, Box<dyn std::error::Error>> {
-        let client = SRUNClient::get_client(self.options.interface.clone())?;
-        let mut u = Url::parse(&self.server)?;
-        u = u.join("/cgi-bin/srun_portal")?;
-
-        let username = self.credentials.clone().unwrap().username;
-
-        let resp = client
-            .get(u.as_str())
-            .query(&[
-                ("callback", "FuckSRUNJsonP"),
-                ("action", "logout"),
-                ("username", &username),
-                ("ac_id", &ac_id),
-                ("ip", &ip),
-            ])
-            .send()?;
-        if resp.status().as_u16() != 200 {
-            let status = resp.status().as_u16();
-            return Err(Box::new(SRUNClientError {
-                message: String::from(format!("Server responded with code {status}")),
-            }));
+        mut f: impl FnMut() -> Result<T, SRUNClientError>,
+    ) -> Result<T, SRUNClientError> {
+        let policy = &self.options.retry;
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if attempt < policy.retries && policy.is_retryable(&e) => {
+                    log::info!("Retrying after error: {}", e);
+                    thread::sleep(policy.backoff.saturating_mul(1 << attempt.min(16)));
+                    attempt += 1;
+                }
+                result => return result,
+            }
         }
-        let content = resp.text()?;
+    }
 
-        Ok(SRUNLogoutResponse::from_string(SRUNClient::extract_jsonp(
-            content,
-        )?)?)
+    fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<HttpResponse, SRUNClientError> {
+        let u = self.endpoint(path)?;
+        self.execute(&HttpRequest::new(u, query))
     }
 
-    fn extract_jsonp(s: String) -> Result<String, Box<dyn std::error::Error>> {
-        let mut content = s;
-        if let (Some(start), Some(end)) = (content.find('('), content.rfind(')')) {
-            content = content[start + 1..end].trim().to_string();
-        } else {
-            return Err(Box::new(SRUNClientError {
-                message: String::from(format!("Invalid json format {content}")),
-            }));
+    // Only the url is logged, the query carries the password hash and the encrypted info
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        let started = Instant::now();
+        let result = self.transport.execute(request);
+        let elapsed = started.elapsed().as_millis();
+        match &result {
+            Ok(resp) => log::debug!("GET {} {} in {} ms", request.url, resp.status, elapsed),
+            Err(e) => log::debug!("GET {} failed in {} ms: {}", request.url, elapsed, e),
         }
-        Ok(content)
+        result
     }
 
-    fn get_client(
-        interface: Option<String>,
-    ) -> Result<reqwest::blocking::Client, Box<dyn std::error::Error>> {
-        let mut client_builder = reqwest::blocking::Client::builder();
-        client_builder = client_builder.redirect(reqwest::redirect::Policy::none());
-        // Try to find a local address under speficied interface
-        match interface {
-            Some(interface) => {
-                let mut address_map: HashMap<String, HashSet<IpAddr>> = HashMap::new();
-                let network_interfaces =
-                    list_afinet_netifas().expect("Failed to get network interfaces");
-                for (name, ip) in network_interfaces.iter() {
-                    if !address_map.contains_key(name) {
-                        address_map.insert(name.clone(), HashSet::new());
-                    }
-                    address_map.get_mut(name).unwrap().insert(ip.clone());
-                }
+    fn get_jsonp(&self, path: &str, query: &[(&str, &str)]) -> Result<String, SRUNClientError> {
+        let request = HttpRequest::new(self.endpoint(path)?, query);
+        self.send_jsonp(&request)
+    }
 
-                if address_map.contains_key(&interface) {
-                    let ips = address_map.get_mut(&interface).unwrap();
-                    let mut has_ipv4 = false;
-                    for ip in ips.iter() {
-                        if ip.is_ipv4() {
-                            has_ipv4 = true;
-                            client_builder = client_builder.local_address(ip.clone());
-                            break;
-                        }
-                    }
-                    if !has_ipv4 {
-                        // If no ipv4 address is found, use the first ipv6 address instead
-                        client_builder =
-                            client_builder.local_address(ips.iter().next().unwrap().clone());
-                    }
-                } else {
-                    return Err(Box::new(SRUNClientError {
-                        message: String::from(format!("Interface {} not found", interface)),
-                    }));
-                }
-            }
-            None => {}
+    fn send_jsonp(&self, request: &HttpRequest) -> Result<String, SRUNClientError> {
+        let resp = self.execute(request)?;
+        if resp.status != 200 {
+            return Err(SRUNClientError::Http {
+                status: resp.status,
+            });
         }
+        SRUNClient::extract_jsonp(resp.body)
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url, SRUNClientError> {
+        // Without a trailing slash join() would replace the last segment of a path prefix
+        let base = if self.server.ends_with('/') {
+            self.server.clone()
+        } else {
+            format!("{}/", self.server)
+        };
+        Url::parse(&base)
+            .and_then(|u| u.join(path))
+            .map_err(|e| SRUNClientError::InvalidUrl {
+                url: self.server.clone(),
+                message: e.to_string(),
+            })
+    }
+
+    fn require_credentials(&self) -> Result<&SRUNClientCredentials, SRUNClientError> {
+        self.credentials
+            .as_ref()
+            .ok_or(SRUNClientError::MissingCredentials)
+    }
 
-        Ok(client_builder.build()?)
+    fn extract_jsonp(s: String) -> Result<String, SRUNClientError> {
+        let mut content = s;
+        if let (Some(start), Some(end)) = (content.find('('), content.rfind(')')) {
+            content = content[start + 1..end].trim().to_string();
+        } else {
+            return Err(SRUNClientError::Parse {
+                message: String::from("not a JSONP response"),
+                body: content,
+            });
+        }
+        Ok(content)
     }
 
-    fn calculate_password_hash(password: &String, challenge: &String) -> String {
+    /// HMAC-MD5 of `password` keyed with `challenge`, sent as `{MD5}<hash>`.
+    pub fn calculate_password_hash(password: &str, challenge: &str) -> String {
         let mut mac = HmacMd5::new_from_slice(challenge.as_bytes()).unwrap();
         mac.update(password.as_bytes());
         let result = mac.finalize();
@@ -265,29 +793,31 @@ impl SRUNClient {
         format!("{:x}", bytes)
     }
 
+    /// Computes the `chksum` parameter of the login request, `i` being the `{SRBX1}` info field.
     pub fn generate_checksum(
-        challenge: &String,
-        username: &String,
-        hmac: &String,
-        ac_id: &String,
-        ip: &String,
-        i: &String,
+        challenge: &str,
+        username: &str,
+        hmac: &str,
+        ac_id: &str,
+        ip: &str,
+        i: &str,
+        portal: &PortalProfile,
     ) -> String {
         let hash_string = String::from_iter(vec![
-            challenge.clone(),
-            username.clone(),
-            challenge.clone(),
-            hmac.clone(),
-            challenge.clone(),
-            ac_id.clone(),
-            challenge.clone(),
-            ip.clone(),
-            challenge.clone(),
-            "200".to_string(),
-            challenge.clone(),
-            "1".to_string(),
-            challenge.clone(),
-            i.clone(),
+            challenge,
+            username,
+            challenge,
+            hmac,
+            challenge,
+            ac_id,
+            challenge,
+            ip,
+            challenge,
+            &portal.n,
+            challenge,
+            &portal.login_type,
+            challenge,
+            i,
         ]);
         let mut hasher = Sha1::new();
         hasher.update(hash_string);
@@ -296,3 +826,15 @@ impl SRUNClient {
         hex_str
     }
 }
+
+// Adds the trace and the recording the options ask for
+fn wrap_transport(transport: ReqwestTransport, options: &SRUNClientOptions) -> Box<dyn Transport> {
+    let transport: Box<dyn Transport> = match &options.trace {
+        Some(sink) => Box::new(TraceTransport::new(transport, sink.clone())),
+        None => Box::new(transport),
+    };
+    match &options.record {
+        Some(recorder) => Box::new(RecordingTransport::new(transport, recorder.clone())),
+        None => transport,
+    }
+}
@@ -0,0 +1,298 @@
+use super::response::SRUNResponse;
+use super::transport::{HttpRequest, HttpResponse, Transport};
+use json::{object, JsonValue};
+use reqwest::Url;
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A single check telling whether the network beyond the gateway is reachable.
+///
+/// Probes parse from strings, as accepted by `--probe`:
+///
+/// - `http:URL` or `http:STATUS:URL`: `URL` answers with `STATUS` (204 by default)
+/// - `body:URL TEXT`: `URL` answers 200 with a body containing `TEXT`
+/// - `dns:HOST`: `HOST` resolves
+/// - `tcp:HOST:PORT`: a TCP connection to `HOST:PORT` succeeds
+///
+/// Captive portals often answer DNS queries themselves, so a DNS probe alone is weak evidence.
+/// HTTP probes go through the client's transport and so its interface, DNS and TCP probes use
+/// the system resolver and routing table instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Probe {
+    HttpStatus { url: String, status: u16 },
+    HttpBody { url: String, contains: String },
+    Dns { host: String },
+    Tcp { address: String },
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Probe::HttpStatus { url, status } => write!(f, "http:{status}:{url}"),
+            Probe::HttpBody { url, contains } => write!(f, "body:{url} {contains}"),
+            Probe::Dns { host } => write!(f, "dns:{host}"),
+            Probe::Tcp { address } => write!(f, "tcp:{address}"),
+        }
+    }
+}
+
+impl FromStr for Probe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid probe {s}");
+        let (kind, rest) = s.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "http" => {
+                let (status, url) = match rest.split_once(':') {
+                    Some((status, url)) if status.chars().all(|c| c.is_ascii_digit()) => {
+                        (status.parse().map_err(|_| invalid())?, url)
+                    }
+                    _ => (204, rest),
+                };
+                Ok(Probe::HttpStatus {
+                    url: url.to_string(),
+                    status,
+                })
+            }
+            "body" => match rest.split_once(' ') {
+                Some((url, contains)) if !contains.is_empty() => Ok(Probe::HttpBody {
+                    url: url.to_string(),
+                    contains: contains.to_string(),
+                }),
+                _ => Err(invalid()),
+            },
+            "dns" if !rest.is_empty() => Ok(Probe::Dns {
+                host: rest.to_string(),
+            }),
+            "tcp" if rest.contains(':') => Ok(Probe::Tcp {
+                address: rest.to_string(),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How many probes must succeed for the network to count as online.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Quorum {
+    Any,
+    All,
+    /// More than half of the probes.
+    #[default]
+    Majority,
+    AtLeast(usize),
+}
+
+impl Quorum {
+    /// Checks that the quorum can be met by `probes` probes, a count of 0 or more than there
+    /// are probes would decide the outcome regardless of the results.
+    pub fn validate(&self, probes: usize) -> Result<(), String> {
+        match self {
+            Quorum::AtLeast(0) => Err(String::from("Quorum must be at least 1")),
+            Quorum::AtLeast(n) if *n > probes => Err(format!(
+                "Quorum {n} cannot be met by {probes} probe{}",
+                if probes == 1 { "" } else { "s" }
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_met(&self, passed: usize, total: usize) -> bool {
+        match self {
+            Quorum::Any => passed > 0,
+            Quorum::All => passed == total,
+            Quorum::Majority => passed * 2 > total,
+            Quorum::AtLeast(n) => passed >= *n,
+        }
+    }
+}
+
+impl fmt::Display for Quorum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quorum::Any => write!(f, "any"),
+            Quorum::All => write!(f, "all"),
+            Quorum::Majority => write!(f, "majority"),
+            Quorum::AtLeast(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+impl FromStr for Quorum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Quorum::Any),
+            "all" => Ok(Quorum::All),
+            "majority" => Ok(Quorum::Majority),
+            n => n.parse().map(Quorum::AtLeast).map_err(|_| {
+                format!("Invalid quorum {s}, expected any, all, majority or a number")
+            }),
+        }
+    }
+}
+
+/// Runs a set of probes and decides by quorum whether the network is reachable.
+#[derive(Debug, Clone)]
+pub struct ConnectivityChecker {
+    probes: Vec<Probe>,
+    quorum: Quorum,
+    connect_timeout: Option<Duration>,
+}
+
+impl ConnectivityChecker {
+    pub fn new(probes: Vec<Probe>, quorum: Quorum) -> ConnectivityChecker {
+        ConnectivityChecker {
+            probes,
+            quorum,
+            connect_timeout: None,
+        }
+    }
+
+    /// Limits the time spent on TCP probes, HTTP probes use the transport's own timeouts.
+    pub fn with_connect_timeout(
+        mut self,
+        connect_timeout: Option<Duration>,
+    ) -> ConnectivityChecker {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Runs every probe, sending HTTP probes through `transport`.
+    pub fn check(&self, transport: &dyn Transport) -> ConnectivityReport {
+        let results: Vec<ProbeResult> = self
+            .probes
+            .iter()
+            .map(|probe| ProbeResult {
+                probe: probe.clone(),
+                result: self.run(probe, transport),
+            })
+            .collect();
+        let passed = results.iter().filter(|r| r.passed()).count();
+        ConnectivityReport {
+            online: self.quorum.is_met(passed, results.len()),
+            quorum: self.quorum,
+            results,
+        }
+    }
+
+    fn run(&self, probe: &Probe, transport: &dyn Transport) -> Result<bool, String> {
+        match probe {
+            Probe::HttpStatus { url, status } => {
+                let resp = http_get(url, transport)?;
+                Ok(resp.status == *status)
+            }
+            Probe::HttpBody { url, contains } => {
+                let resp = http_get(url, transport)?;
+                Ok(resp.status == 200 && resp.body.contains(contains.as_str()))
+            }
+            Probe::Dns { host } => {
+                let mut addrs = (host.as_str(), 0)
+                    .to_socket_addrs()
+                    .map_err(|e| e.to_string())?;
+                Ok(addrs.next().is_some())
+            }
+            Probe::Tcp { address } => {
+                let addrs: Vec<_> = address
+                    .to_socket_addrs()
+                    .map_err(|e| e.to_string())?
+                    .collect();
+                let mut last_error = String::from("no address");
+                for addr in addrs {
+                    let connected = match self.connect_timeout {
+                        Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                        None => TcpStream::connect(addr),
+                    };
+                    match connected {
+                        Ok(_) => return Ok(true),
+                        Err(e) => last_error = e.to_string(),
+                    }
+                }
+                Err(last_error)
+            }
+        }
+    }
+}
+
+fn http_get(url: &str, transport: &dyn Transport) -> Result<HttpResponse, String> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+    transport
+        .execute(&HttpRequest::new(url, &[]))
+        .map_err(|e| e.to_string())
+}
+
+/// Outcome of one probe, `Err` holds why the probe got no answer at all.
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub probe: Probe,
+    pub result: Result<bool, String>,
+}
+
+impl ProbeResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.result, Ok(true))
+    }
+}
+
+/// Result of [`ConnectivityChecker::check`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectivityReport {
+    pub online: bool,
+    pub quorum: Quorum,
+    pub results: Vec<ProbeResult>,
+}
+
+impl fmt::Display for ConnectivityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let passed = self.results.iter().filter(|r| r.passed()).count();
+        writeln!(
+            f,
+            "Network Status: {} ({}/{} probes passed, quorum {})",
+            if self.online { "Online" } else { "Offline" },
+            passed,
+            self.results.len(),
+            self.quorum
+        )?;
+        for r in &self.results {
+            match &r.result {
+                Ok(true) => writeln!(f, "  ok    {}", r.probe)?,
+                Ok(false) => writeln!(f, "  fail  {}", r.probe)?,
+                Err(e) => writeln!(f, "  error {}: {}", r.probe, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SRUNResponse for ConnectivityReport {
+    fn to_json(&self) -> String {
+        let probes: Vec<JsonValue> = self
+            .results
+            .iter()
+            .map(|r| {
+                object! {
+                    probe: r.probe.to_string(),
+                    passed: r.passed(),
+                    error: r.result.as_ref().err().cloned(),
+                }
+            })
+            .collect();
+        let obj = object! {
+            online: self.online,
+            quorum: self.quorum.to_string(),
+            probes: probes,
+        };
+        json::stringify(obj)
+    }
+
+    fn is_ok(&self) -> bool {
+        self.online
+    }
+}
@@ -0,0 +1,165 @@
+use super::error::SRUNClientError;
+use super::response::SRUNResponse;
+use super::transport::{HttpRequest, HttpResponse, Transport};
+use json::object;
+use regex::Regex;
+use reqwest::Url;
+use std::fmt;
+
+// Gateways usually redirect straight to the portal, some bounce through a page or two first
+const MAX_REDIRECTS: usize = 5;
+
+/// A portal found by [`discover_portal`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PortalDiscovery {
+    /// Base url of the portal, usable as the client's server.
+    pub server: String,
+    /// `ac_id` carried by the redirect, if any.
+    pub ac_id: Option<String>,
+    /// The last redirect target that was followed.
+    pub location: String,
+}
+
+impl fmt::Display for PortalDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Portal: {}", self.server)?;
+        if let Some(ac_id) = &self.ac_id {
+            writeln!(f, "ac_id: {}", ac_id)?;
+        }
+        writeln!(f, "Redirect: {}", self.location)
+    }
+}
+
+impl SRUNResponse for PortalDiscovery {
+    fn to_json(&self) -> String {
+        let obj = object! {
+            server: self.server.clone(),
+            ac_id: self.ac_id.clone(),
+            location: self.location.clone(),
+        };
+        json::stringify(obj)
+    }
+
+    fn is_ok(&self) -> bool {
+        true
+    }
+}
+
+/// Finds the portal by requesting `probe_url`, which the gateway hijacks while offline, and
+/// following the redirects it answers with until one carries an `ac_id`.
+///
+/// The portal's base url is the final redirect target without its last path segment, e.g.
+/// `http://10.0.0.1/srun_portal_pc?ac_id=1` gives `http://10.0.0.1/`.
+pub fn discover_portal(
+    transport: &dyn Transport,
+    probe_url: &str,
+) -> Result<PortalDiscovery, SRUNClientError> {
+    let mut url = Url::parse(probe_url).map_err(|e| invalid_url(probe_url, e))?;
+    let mut target = None;
+
+    for _ in 0..MAX_REDIRECTS {
+        let resp = transport.execute(&HttpRequest::new(url.clone(), &[]))?;
+        let location = match redirect_target(&resp) {
+            Some(location) => location,
+            None if target.is_none() => {
+                return Err(SRUNClientError::PortalNotFound {
+                    probe_url: probe_url.to_string(),
+                    online: resp.status == 204,
+                })
+            }
+            None => break,
+        };
+        url = url.join(&location).map_err(|e| invalid_url(&location, e))?;
+        target = Some(url.clone());
+        if ac_id_from_url(url.as_str()).is_some() {
+            break;
+        }
+    }
+
+    // The first iteration either returned or set a target
+    let target = target.unwrap();
+    let mut server = target
+        .join("./")
+        .map_err(|e| invalid_url(target.as_str(), e))?;
+    server.set_query(None);
+    Ok(PortalDiscovery {
+        server: server.to_string(),
+        ac_id: ac_id_from_url(target.as_str()),
+        location: target.to_string(),
+    })
+}
+
+/// Where [`SRUNClient::discover_ac_id`](super::client::SRUNClient::discover_ac_id) found the
+/// `ac_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AcIdSource {
+    /// Set explicitly in [`SRUNClientOptions::ac_id`](super::client::SRUNClientOptions::ac_id).
+    Configured,
+    /// Found by [`discover_portal`] and passed on in
+    /// [`SRUNClientOptions::discovered_ac_id`](super::client::SRUNClientOptions::discovered_ac_id).
+    Discovered,
+    /// The `Location` header of the redirect issued by `/index_1.html`.
+    LocationHeader,
+    /// A `<meta http-equiv="refresh">` or JavaScript redirect in the body of `/index_1.html`.
+    HtmlRedirect,
+    /// The redirect the gateway injected into the captive-portal probe.
+    ProbeRedirect,
+    /// Fallback from [`SRUNClientOptions::default_ac_id`](super::client::SRUNClientOptions::default_ac_id).
+    Default,
+}
+
+impl fmt::Display for AcIdSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AcIdSource::Configured => write!(f, "configuration"),
+            AcIdSource::Discovered => write!(f, "portal discovery"),
+            AcIdSource::LocationHeader => write!(f, "Location header"),
+            AcIdSource::HtmlRedirect => write!(f, "HTML redirect"),
+            AcIdSource::ProbeRedirect => write!(f, "captive-portal probe"),
+            AcIdSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+fn invalid_url(url: &str, e: impl fmt::Display) -> SRUNClientError {
+    SRUNClientError::InvalidUrl {
+        url: url.to_string(),
+        message: e.to_string(),
+    }
+}
+
+/// The url `resp` sends the browser to, from a 3xx `Location` header or, failing that, from a
+/// redirect in the HTML body.
+pub fn redirect_target(resp: &HttpResponse) -> Option<String> {
+    if (300..400).contains(&resp.status) {
+        if let Some(location) = resp.header("Location") {
+            return Some(location.to_string());
+        }
+    }
+    html_redirect(&resp.body)
+}
+
+/// Extracts the target of a `<meta http-equiv="refresh">`, `location.href = ...` or
+/// `location.replace(...)` redirect.
+pub fn html_redirect(body: &str) -> Option<String> {
+    let patterns = [
+        r#"(?is)<meta[^>]+http-equiv\s*=\s*["']?refresh["']?[^>]*content\s*=\s*["']?\s*\d*\s*;?\s*url\s*=\s*([^"'>\s]+)"#,
+        r#"(?i)location\.href\s*=\s*["']([^"']+)["']"#,
+        r#"(?i)location\.replace\(\s*["']([^"']+)["']"#,
+        r#"(?i)location\s*=\s*["']([^"']+)["']"#,
+    ];
+    patterns.iter().find_map(|p| {
+        Regex::new(p)
+            .unwrap()
+            .captures(body)
+            .map(|caps| caps[1].replace("&amp;", "&"))
+    })
+}
+
+/// Extracts the `ac_id` query parameter from a url.
+pub fn ac_id_from_url(url: &str) -> Option<String> {
+    let re = Regex::new(r"ac_id=(\d+)").unwrap();
+    re.captures(url).map(|caps| caps[1].to_string())
+}
@@ -1,14 +1,120 @@
+use super::reason::{self, Reason};
 use std::fmt;
 
+/// Error raised by [`SRUNClient`](super::client::SRUNClient) and the response parsers.
 #[derive(Debug)]
-pub struct SRUNClientError {
-    pub message: String,
+#[non_exhaustive]
+pub enum SRUNClientError {
+    /// The server answered with an unexpected HTTP status.
+    Http { status: u16 },
+    /// The request could not be sent or its response could not be read.
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+    /// The server url, or an endpoint derived from it, is not a valid url.
+    InvalidUrl { url: String, message: String },
+    /// The response body is not the JSONP / JSON document we expected.
+    Parse { message: String, body: String },
+    /// A field the client relies on is absent from the decoded response.
+    MissingField { field: String, body: String },
+    /// The portal answered but reported a failure, e.g. rejected credentials.
+    Portal {
+        ecode: Option<String>,
+        error: String,
+        error_msg: Option<String>,
+    },
+    /// The configured network interface is missing or unusable.
+    Interface { interface: String, message: String },
+    /// The portal did not reveal its `ac_id`.
+    AcIdNotFound { location: Option<String> },
+    /// The request needs a username and password but none were configured.
+    MissingCredentials,
+    /// The captive-portal probe was not redirected to a portal, `online` tells whether it
+    /// answered 204.
+    PortalNotFound { probe_url: String, online: bool },
 }
 
 impl fmt::Display for SRUNClientError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "SRUNClientError: {}", self.message)
+        match self {
+            SRUNClientError::Http { status } => write!(f, "Server responded with code {status}"),
+            SRUNClientError::Transport(e) => write!(f, "{e}"),
+            SRUNClientError::InvalidUrl { url, message } => {
+                write!(f, "Invalid url {url}: {message}")
+            }
+            SRUNClientError::Parse { message, body } => {
+                write!(f, "Invalid response ({message}): {body}")
+            }
+            SRUNClientError::MissingField { field, body } => {
+                write!(f, "Response is missing field {field}: {body}")
+            }
+            SRUNClientError::Portal {
+                ecode,
+                error,
+                error_msg,
+            } => {
+                let message = error_msg.as_deref().filter(|m| !m.is_empty());
+                write!(f, "Portal error: {}", message.unwrap_or(error))?;
+                if let Some(ecode) = ecode {
+                    write!(f, " ({ecode})")?;
+                }
+                if let Some(reason) = self.reason() {
+                    write!(f, "\n{reason}")?;
+                }
+                Ok(())
+            }
+            SRUNClientError::Interface { interface, message } => {
+                write!(f, "Interface {interface}: {message}")
+            }
+            SRUNClientError::AcIdNotFound { location } => match location {
+                Some(location) => write!(f, "Unable to find ac_id in redirect to {location}"),
+                None => write!(f, "Unable to find ac_id, portal did not redirect"),
+            },
+            SRUNClientError::MissingCredentials => {
+                write!(f, "Username and password must be provided")
+            }
+            SRUNClientError::PortalNotFound { probe_url, online } => {
+                if *online {
+                    write!(
+                        f,
+                        "Unable to discover portal, {probe_url} is reachable (already online?), \
+                         provide the server url"
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Unable to discover portal, {probe_url} was not redirected"
+                    )
+                }
+            }
+        }
     }
 }
 
-impl std::error::Error for SRUNClientError {}
+impl SRUNClientError {
+    /// Explanation of a [`Portal`](SRUNClientError::Portal) error from the catalog of known
+    /// SRUN errors.
+    pub fn reason(&self) -> Option<&'static Reason> {
+        match self {
+            SRUNClientError::Portal {
+                ecode,
+                error,
+                error_msg,
+            } => reason::lookup(ecode.as_deref(), error, error_msg.as_deref()),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for SRUNClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SRUNClientError::Transport(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SRUNClientError {
+    fn from(e: reqwest::Error) -> Self {
+        SRUNClientError::Transport(Box::new(e))
+    }
+}
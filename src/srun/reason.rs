@@ -0,0 +1,176 @@
+use json::{object, JsonValue};
+use std::fmt;
+
+/// Broad category of a known portal failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReasonKind {
+    BadCredentials,
+    UnknownUser,
+    TooManyDevices,
+    AlreadyOnline,
+    Arrears,
+    AccountDisabled,
+    RateLimited,
+    ChallengeExpired,
+    SignatureMismatch,
+    BasTimeout,
+    AddressUnknown,
+    NotOnline,
+}
+
+impl ReasonKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasonKind::BadCredentials => "bad_credentials",
+            ReasonKind::UnknownUser => "unknown_user",
+            ReasonKind::TooManyDevices => "too_many_devices",
+            ReasonKind::AlreadyOnline => "already_online",
+            ReasonKind::Arrears => "arrears",
+            ReasonKind::AccountDisabled => "account_disabled",
+            ReasonKind::RateLimited => "rate_limited",
+            ReasonKind::ChallengeExpired => "challenge_expired",
+            ReasonKind::SignatureMismatch => "signature_mismatch",
+            ReasonKind::BasTimeout => "bas_timeout",
+            ReasonKind::AddressUnknown => "address_unknown",
+            ReasonKind::NotOnline => "not_online",
+        }
+    }
+}
+
+/// Explanation of a portal `ecode` / `error` / `error_msg`, with what to do about it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reason {
+    pub kind: ReasonKind,
+    pub explanation: &'static str,
+    pub action: &'static str,
+    ecodes: &'static [&'static str],
+    messages: &'static [&'static str],
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}. {}", self.explanation, self.action)
+    }
+}
+
+impl From<&Reason> for JsonValue {
+    fn from(reason: &Reason) -> Self {
+        object! {
+            kind: reason.kind.as_str(),
+            explanation: reason.explanation,
+            action: reason.action,
+        }
+    }
+}
+
+const CATALOG: &[Reason] = &[
+    Reason {
+        kind: ReasonKind::BadCredentials,
+        explanation: "The password was rejected",
+        action: "Check the password, it may have been changed or expired",
+        ecodes: &["E2553", "E2901"],
+        messages: &["Password is error", "password_error"],
+    },
+    Reason {
+        kind: ReasonKind::UnknownUser,
+        explanation: "The portal does not know this username",
+        action: "Check the username, some portals expect a suffix such as @cmcc",
+        ecodes: &["E2531"],
+        messages: &["User not found"],
+    },
+    Reason {
+        kind: ReasonKind::TooManyDevices,
+        explanation: "The account already has the maximum number of devices online",
+        action: "Log out another device, e.g. from the self-service page, and retry",
+        ecodes: &["E2620"],
+        messages: &["online_num_error", "too many online"],
+    },
+    Reason {
+        kind: ReasonKind::AlreadyOnline,
+        explanation: "This address already has a session",
+        action: "Nothing to do, or log out first to switch accounts",
+        ecodes: &[],
+        messages: &["ip_already_online_error"],
+    },
+    Reason {
+        kind: ReasonKind::Arrears,
+        explanation: "The account is in arrears or out of traffic",
+        action: "Top up the account balance",
+        ecodes: &["E2616"],
+        messages: &["Arrearage users", "arrearage", "balance_error"],
+    },
+    Reason {
+        kind: ReasonKind::AccountDisabled,
+        explanation: "The account is disabled",
+        action: "Contact the network administrator",
+        ecodes: &["E2606"],
+        messages: &["User is disabled"],
+    },
+    Reason {
+        kind: ReasonKind::ChallengeExpired,
+        explanation: "The login challenge expired before it was used",
+        action: "Retry, a fresh challenge is fetched automatically",
+        ecodes: &[],
+        messages: &["challenge_expire_error"],
+    },
+    Reason {
+        kind: ReasonKind::RateLimited,
+        explanation: "Logins were attempted too quickly",
+        action: "Wait a few seconds before retrying",
+        ecodes: &["E2532", "E2533"],
+        messages: &["authentication interval", "too frequent"],
+    },
+    Reason {
+        kind: ReasonKind::SignatureMismatch,
+        explanation: "The portal rejected the request signature",
+        action: "Check the portal profile (n, type, enc_ver) against the portal's login page",
+        ecodes: &[],
+        messages: &["sign_error"],
+    },
+    Reason {
+        kind: ReasonKind::BasTimeout,
+        explanation: "The portal's access server (BAS) did not respond",
+        action: "Retry with --redirect to trigger the captive portal first, or wait and retry",
+        ecodes: &[],
+        messages: &["BAS respond timeout"],
+    },
+    Reason {
+        kind: ReasonKind::AddressUnknown,
+        explanation: "The portal does not know this IP address",
+        action: "Renew the DHCP lease or check --interface",
+        ecodes: &["E2833"],
+        messages: &["not in the dhcp table"],
+    },
+    Reason {
+        kind: ReasonKind::NotOnline,
+        explanation: "There is no session for this address",
+        action: "Log in",
+        ecodes: &[],
+        messages: &["not_online_error"],
+    },
+];
+
+/// Looks up the portal's failure in the catalog of known SRUN errors.
+///
+/// Messages are more specific than codes, some codes are reused for unrelated errors, so they
+/// are matched first.
+pub fn lookup(
+    ecode: Option<&str>,
+    error: &str,
+    error_msg: Option<&str>,
+) -> Option<&'static Reason> {
+    let texts = [Some(error), error_msg];
+    CATALOG
+        .iter()
+        .find(|r| {
+            texts.iter().flatten().any(|text| {
+                let text = text.to_lowercase();
+                r.messages.iter().any(|m| text.contains(&m.to_lowercase()))
+            })
+        })
+        .or_else(|| {
+            let ecode = ecode?;
+            CATALOG.iter().find(|r| r.ecodes.contains(&ecode))
+        })
+}
@@ -1,15 +1,80 @@
 use core::fmt;
 
 use fmt::{Debug, Display};
-use json::{self, object};
+use json::{self, object, JsonValue};
 
+use super::error::SRUNClientError;
+use super::reason::{self, Reason};
+
+/// Common behaviour of every decoded portal response.
+///
+/// `Display` renders a short human readable summary.
 pub trait SRUNResponse: Debug + Display {
+    /// Serializes every field of the response as a JSON object.
     fn to_json(&self) -> String;
+    /// Whether the portal reported `error: "ok"`.
+    fn is_ok(&self) -> bool;
+}
+
+/// Responses of the IPv4 session and, for dual-stack clients, of the separate IPv6 session.
+#[derive(Debug)]
+pub struct SRUNDualStackResponse<T: SRUNResponse> {
+    pub ipv4: T,
+    pub ipv6: Option<T>,
+}
+
+impl<T: SRUNResponse> fmt::Display for SRUNDualStackResponse<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.ipv6 {
+            Some(ipv6) => write!(f, "[IPv4]\n{}[IPv6]\n{}", self.ipv4, ipv6),
+            None => write!(f, "{}", self.ipv4),
+        }
+    }
+}
+
+impl<T: SRUNResponse> SRUNResponse for SRUNDualStackResponse<T> {
+    fn to_json(&self) -> String {
+        let parse = |r: &T| json::parse(&r.to_json()).unwrap_or(JsonValue::Null);
+        let obj = object! {
+            ipv4: parse(&self.ipv4),
+            ipv6: self.ipv6.as_ref().map(parse),
+        };
+        json::stringify(obj)
+    }
+
+    fn is_ok(&self) -> bool {
+        self.ipv4.is_ok() && self.ipv6.as_ref().is_none_or(|r| r.is_ok())
+    }
 }
 
-// TODO: Figure out types of numberic fields (Since my institution doesn't use them)
+/// Error code reported by the portal, either numeric (`0`) or textual (`E2901`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ECode {
+    Number(u64),
+    Text(String),
+}
+
+impl fmt::Display for ECode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ECode::Number(n) => write!(f, "{n}"),
+            ECode::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
 
+impl From<ECode> for JsonValue {
+    fn from(ecode: ECode) -> Self {
+        match ecode {
+            ECode::Number(n) => n.into(),
+            ECode::Text(s) => s.into(),
+        }
+    }
+}
+
+/// Response of `rad_user_info`, most fields are only present when the user is online.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct SRUNQueryResponse {
     // Common response
     pub error: String,
@@ -17,7 +82,7 @@ pub struct SRUNQueryResponse {
 
     // When user not online
     pub client_ip: Option<String>,
-    pub ecode: Option<u64>,
+    pub ecode: Option<ECode>,
     pub error_msg: Option<String>,
     pub res: Option<String>,
     pub srun_ver: Option<String>,
@@ -45,26 +110,33 @@ pub struct SRUNQueryResponse {
     pub sum_bytes: Option<u64>,
     pub sum_seconds: Option<u64>,
     pub sysver: Option<String>,
-    pub user_balance: Option<u64>,
-    pub user_charge: Option<u64>,
+    pub user_balance: Option<f64>,
+    pub user_charge: Option<f64>,
     pub user_mac: Option<String>,
     pub user_name: Option<String>,
     pub wallet_balance: Option<String>,
+
+    /// The JSON document the response was decoded from.
+    pub raw: String,
 }
 
 impl fmt::Display for SRUNQueryResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.error == "ok" {
-            write!(f, "Login Status: OK\n")?;
+            writeln!(f, "Login Status: OK")?;
         } else {
-            write!(f, "Login Status: No\n")?;
+            writeln!(f, "Login Status: No")?;
         }
-        write!(f, "Current IP: {}\n", self.online_ip)?;
-        match self.online_device_total.clone() {
-            Some(count) => {
-                write!(f, "{} Devices Online\n", count)?;
-            }
-            None => {}
+        writeln!(f, "Current IP: {}", self.online_ip)?;
+        if let Some(ip6) = self
+            .online_ip6
+            .as_deref()
+            .filter(|ip| !ip.is_empty() && *ip != "::")
+        {
+            writeln!(f, "Current IPv6: {}", ip6)?;
+        }
+        if let Some(count) = self.online_device_total.clone() {
+            writeln!(f, "{} Devices Online", count)?;
         }
 
         Ok(())
@@ -81,100 +153,121 @@ impl SRUNResponse for SRUNQueryResponse {
             error_msg: self.error_msg.clone(),
             res: self.res.clone(),
             srun_ver: self.srun_ver.clone(),
-            st: self.st.clone(),
-            server_flag: self.server_flag.clone(),
-            add_time: self.add_time.clone(),
-            all_bytes: self.all_bytes.clone(),
+            st: self.st,
+            server_flag: self.server_flag,
+            add_time: self.add_time,
+            all_bytes: self.all_bytes,
             billing_name: self.billing_name.clone(),
-            bytes_in: self.bytes_in.clone(),
-            bytes_out: self.bytes_out.clone(),
-            checkout_date: self.checkout_date.clone(),
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            checkout_date: self.checkout_date,
             domain: self.domain.clone(),
             group_id: self.group_id.clone(),
-            keepalive_time: self.keepalive_time.clone(),
+            keepalive_time: self.keepalive_time,
             online_device_total: self.online_device_total.clone(),
             online_ip6: self.online_ip6.clone(),
             package_id: self.package_id.clone(),
             products_id: self.products_id.clone(),
             products_name: self.products_name.clone(),
             real_name: self.real_name.clone(),
-            remain_bytes: self.remain_bytes.clone(),
-            remain_seconds: self.remain_seconds.clone(),
-            sum_bytes: self.sum_bytes.clone(),
-            sum_seconds: self.sum_seconds.clone(),
+            remain_bytes: self.remain_bytes,
+            remain_seconds: self.remain_seconds,
+            sum_bytes: self.sum_bytes,
+            sum_seconds: self.sum_seconds,
             sysver: self.sysver.clone(),
-            user_balance: self.user_balance.clone(),
-            user_charge: self.user_charge.clone(),
+            user_balance: self.user_balance,
+            user_charge: self.user_charge,
             user_mac: self.user_mac.clone(),
             user_name: self.user_name.clone(),
             wallet_balance: self.wallet_balance.clone(),
+            reason: self.reason().map(JsonValue::from),
         };
         json::stringify(obj)
     }
+
+    fn is_ok(&self) -> bool {
+        self.error == "ok"
+    }
 }
 
 impl SRUNQueryResponse {
-    pub fn from_string(s: String) -> Result<SRUNQueryResponse, Box<dyn std::error::Error>> {
-        let j = json::parse(&s)?;
+    /// Explanation of the failure from the catalog of known SRUN errors, `None` when ok.
+    pub fn reason(&self) -> Option<&'static Reason> {
+        failure_reason(&self.error, &self.ecode, &self.error_msg)
+    }
+
+    /// Decodes the JSON payload extracted from the JSONP response.
+    pub fn from_string(s: String) -> Result<SRUNQueryResponse, SRUNClientError> {
+        let j = parse_json(&s)?;
 
         Ok(SRUNQueryResponse {
-            error: j["error"].as_str().unwrap().to_string(),
-            online_ip: j["online_ip"].as_str().unwrap().to_string(),
-            client_ip: j["client_ip"].as_str().map(String::from),
-            ecode: j["ecode"].as_u64(),
-            error_msg: j["error_msg"].as_str().map(String::from),
-            res: j["res"].as_str().map(String::from),
-            srun_ver: j["srun_ver"].as_str().map(String::from),
-            st: j["st"].as_u64(),
-            server_flag: j["ServerFlag"].as_u64(),
-            add_time: j["add_time"].as_u64(),
-            all_bytes: j["all_bytes"].as_u64(),
-            billing_name: j["billing_name"].as_str().map(String::from),
-            bytes_in: j["bytes_in"].as_u64(),
-            bytes_out: j["bytes_out"].as_u64(),
-            checkout_date: j["checkout_date"].as_u64(),
-            domain: j["domain"].as_str().map(String::from),
-            group_id: j["group_id"].as_str().map(String::from),
-            keepalive_time: j["keepalive_time"].as_u64(),
-            online_device_total: j["online_device_total"].as_str().map(String::from),
-            online_ip6: j["online_ip6"].as_str().map(String::from),
-            package_id: j["package_id"].as_str().map(String::from),
-            products_id: j["products_id"].as_str().map(String::from),
-            products_name: j["products_name"].as_str().map(String::from),
-            real_name: j["real_name"].as_str().map(String::from),
-            remain_bytes: j["remain_bytes"].as_u64(),
-            remain_seconds: j["remain_seconds"].as_u64(),
-            sum_bytes: j["sum_bytes"].as_u64(),
-            sum_seconds: j["sum_seconds"].as_u64(),
-            sysver: j["sysver"].as_str().map(String::from),
-            user_balance: j["user_balance"].as_u64(),
-            user_charge: j["user_charge"].as_u64(),
-            user_mac: j["user_mac"].as_str().map(String::from),
-            user_name: j["user_name"].as_str().map(String::from),
-            wallet_balance: j["wallet_balance"].as_str().map(String::from),
+            error: required_string(&j, "error", &s)?,
+            online_ip: required_string(&j, "online_ip", &s)?,
+            client_ip: optional_string(&j, "client_ip"),
+            ecode: optional_ecode(&j),
+            error_msg: optional_string(&j, "error_msg"),
+            res: optional_string(&j, "res"),
+            srun_ver: optional_string(&j, "srun_ver"),
+            st: optional_u64(&j, "st"),
+            server_flag: optional_u64(&j, "ServerFlag"),
+            add_time: optional_u64(&j, "add_time"),
+            all_bytes: optional_u64(&j, "all_bytes"),
+            billing_name: optional_string(&j, "billing_name"),
+            bytes_in: optional_u64(&j, "bytes_in"),
+            bytes_out: optional_u64(&j, "bytes_out"),
+            checkout_date: optional_u64(&j, "checkout_date"),
+            domain: optional_string(&j, "domain"),
+            group_id: optional_string(&j, "group_id"),
+            keepalive_time: optional_u64(&j, "keepalive_time"),
+            online_device_total: optional_string(&j, "online_device_total"),
+            online_ip6: optional_string(&j, "online_ip6"),
+            package_id: optional_string(&j, "package_id"),
+            products_id: optional_string(&j, "products_id"),
+            products_name: optional_string(&j, "products_name"),
+            real_name: optional_string(&j, "real_name"),
+            remain_bytes: optional_u64(&j, "remain_bytes"),
+            remain_seconds: optional_u64(&j, "remain_seconds"),
+            sum_bytes: optional_u64(&j, "sum_bytes"),
+            sum_seconds: optional_u64(&j, "sum_seconds"),
+            sysver: optional_string(&j, "sysver"),
+            user_balance: optional_f64(&j, "user_balance"),
+            user_charge: optional_f64(&j, "user_charge"),
+            user_mac: optional_string(&j, "user_mac"),
+            user_name: optional_string(&j, "user_name"),
+            wallet_balance: optional_string(&j, "wallet_balance"),
+            raw: s,
         })
     }
 }
 
+/// Response of `get_challenge`.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct SRUNChallengeResponse {
     pub challenge: String,
-    pub client_ip: String,
-    pub ecode: u64,
+    pub client_ip: Option<String>,
+    pub ecode: Option<ECode>,
     pub error: String,
-    pub error_msg: String,
-    pub expire: String,
-    pub online_ip: String,
-    pub res: String,
-    pub srun_ver: String,
-    pub st: u64,
+    pub error_msg: Option<String>,
+    pub expire: Option<String>,
+    pub online_ip: Option<String>,
+    pub res: Option<String>,
+    pub srun_ver: Option<String>,
+    pub st: Option<u64>,
+
+    /// The JSON document the response was decoded from.
+    pub raw: String,
 }
 
 impl fmt::Display for SRUNChallengeResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Current IP: {}\n", self.online_ip)?;
-        write!(f, "Challenge: {}\n", self.challenge)?;
-        write!(f, "Expires in {} seconds\n", self.expire)?;
+        if let Some(online_ip) = &self.online_ip {
+            writeln!(f, "Current IP: {}", online_ip)?;
+        }
+        writeln!(f, "Challenge: {}", self.challenge)?;
+        if let Some(expire) = &self.expire {
+            writeln!(f, "Expires in {} seconds", expire)?;
+        }
         Ok(())
     }
 }
@@ -191,40 +284,54 @@ impl SRUNResponse for SRUNChallengeResponse {
             online_ip: self.online_ip.clone(),
             res: self.res.clone(),
             srun_ver: self.srun_ver.clone(),
-            st: self.st.clone(),
+            st: self.st,
+            reason: self.reason().map(JsonValue::from),
         };
         json::stringify(obj)
     }
+
+    fn is_ok(&self) -> bool {
+        self.error == "ok"
+    }
 }
 
 impl SRUNChallengeResponse {
-    pub fn from_string(s: String) -> Result<SRUNChallengeResponse, Box<dyn std::error::Error>> {
-        let j = json::parse(&s)?;
+    /// Explanation of the failure from the catalog of known SRUN errors, `None` when ok.
+    pub fn reason(&self) -> Option<&'static Reason> {
+        failure_reason(&self.error, &self.ecode, &self.error_msg)
+    }
+
+    /// Decodes the JSON payload extracted from the JSONP response.
+    pub fn from_string(s: String) -> Result<SRUNChallengeResponse, SRUNClientError> {
+        let j = parse_json(&s)?;
 
         Ok(SRUNChallengeResponse {
-            challenge: j["challenge"].as_str().unwrap().to_string(),
-            client_ip: j["client_ip"].as_str().unwrap().to_string(),
-            ecode: j["ecode"].as_u64().unwrap(),
-            error: j["error"].as_str().unwrap().to_string(),
-            error_msg: j["error_msg"].as_str().unwrap().to_string(),
-            expire: j["expire"].as_str().unwrap().to_string(),
-            online_ip: j["online_ip"].as_str().unwrap().to_string(),
-            res: j["res"].as_str().unwrap().to_string(),
-            srun_ver: j["srun_ver"].as_str().unwrap().to_string(),
-            st: j["st"].as_u64().unwrap(),
+            challenge: required_string(&j, "challenge", &s)?,
+            client_ip: optional_string(&j, "client_ip"),
+            ecode: optional_ecode(&j),
+            error: required_string(&j, "error", &s)?,
+            error_msg: optional_string(&j, "error_msg"),
+            expire: optional_string(&j, "expire"),
+            online_ip: optional_string(&j, "online_ip"),
+            res: optional_string(&j, "res"),
+            srun_ver: optional_string(&j, "srun_ver"),
+            st: optional_u64(&j, "st"),
+            raw: s,
         })
     }
 }
 
+/// Response of `srun_portal` with `action=login`.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct SRUNLoginResponse {
-    pub client_ip: String,
-    pub ecode: Option<u64>,
+    pub client_ip: Option<String>,
+    pub ecode: Option<ECode>,
     pub error: String,
-    pub error_msg: String,
-    pub online_ip: String,
-    pub res: String,
-    pub srun_ver: String,
+    pub error_msg: Option<String>,
+    pub online_ip: Option<String>,
+    pub res: Option<String>,
+    pub srun_ver: Option<String>,
 
     pub st: Option<u64>,
     pub server_flag: Option<u64>,
@@ -239,17 +346,29 @@ pub struct SRUNLoginResponse {
     pub suc_msg: Option<String>,
     pub sysver: Option<String>,
     pub username: Option<String>,
-    pub wallet_balance: Option<u64>,
+    pub wallet_balance: Option<f64>,
+
+    /// The JSON document the response was decoded from.
+    pub raw: String,
 }
 
 impl fmt::Display for SRUNLoginResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.error == "ok" {
-            write!(f, "Login OK\n")?;
+            writeln!(f, "Login OK")?;
         } else {
-            write!(f, "Login Failed: {}\n", self.error_msg)?;
+            writeln!(
+                f,
+                "Login Failed: {}",
+                self.error_msg.as_deref().unwrap_or(&self.error)
+            )?;
+            if let Some(reason) = self.reason() {
+                writeln!(f, "{}", reason)?;
+            }
+        }
+        if let Some(online_ip) = &self.online_ip {
+            writeln!(f, "Current IP: {}", online_ip)?;
         }
-        write!(f, "Current IP: {}\n", self.online_ip)?;
         Ok(())
     }
 }
@@ -264,72 +383,96 @@ impl SRUNResponse for SRUNLoginResponse {
             online_ip: self.online_ip.clone(),
             res: self.res.clone(),
             srun_ver: self.srun_ver.clone(),
-            st: self.st.clone(),
-            server_flag: self.server_flag.clone(),
+            st: self.st,
+            server_flag: self.server_flag,
             services_intf_server_ip: self.services_intf_server_ip.clone(),
             services_intf_server_port: self.services_intf_server_port.clone(),
             access_token: self.access_token.clone(),
-            checkout_date: self.checkout_date.clone(),
+            checkout_date: self.checkout_date,
             poly_msg: self.poly_msg.clone(),
             real_name: self.real_name.clone(),
-            remain_flux: self.remain_flux.clone(),
-            remain_times: self.remain_times.clone(),
+            remain_flux: self.remain_flux,
+            remain_times: self.remain_times,
             suc_msg: self.suc_msg.clone(),
             sysver: self.sysver.clone(),
             username: self.username.clone(),
-            wallet_balance: self.wallet_balance.clone(),
+            wallet_balance: self.wallet_balance,
+            reason: self.reason().map(JsonValue::from),
         };
         json::stringify(obj)
     }
+
+    fn is_ok(&self) -> bool {
+        self.error == "ok"
+    }
 }
 
 impl SRUNLoginResponse {
-    pub fn from_string(s: String) -> Result<SRUNLoginResponse, Box<dyn std::error::Error>> {
-        let j = json::parse(&s)?;
+    /// Explanation of the failure from the catalog of known SRUN errors, `None` when ok.
+    pub fn reason(&self) -> Option<&'static Reason> {
+        failure_reason(&self.error, &self.ecode, &self.error_msg)
+    }
+
+    /// Decodes the JSON payload extracted from the JSONP response.
+    pub fn from_string(s: String) -> Result<SRUNLoginResponse, SRUNClientError> {
+        let j = parse_json(&s)?;
 
         Ok(SRUNLoginResponse {
-            client_ip: j["client_ip"].as_str().unwrap().to_string(),
-            ecode: j["ecode"].as_u64(),
-            error: j["error"].as_str().unwrap().to_string(),
-            error_msg: j["error_msg"].as_str().unwrap().to_string(),
-            online_ip: j["online_ip"].as_str().unwrap().to_string(),
-            res: j["res"].as_str().unwrap().to_string(),
-            srun_ver: j["srun_ver"].as_str().unwrap().to_string(),
-            st: j["st"].as_u64(),
-            server_flag: j["ServerFlag"].as_u64(),
-            services_intf_server_ip: j["services_intf_server_ip"].as_str().map(String::from),
-            services_intf_server_port: j["services_intf_server_port"].as_str().map(String::from),
-            access_token: j["access_token"].as_str().map(String::from),
-            checkout_date: j["checkout_date"].as_u64(),
-            poly_msg: j["poly_msg"].as_str().map(String::from),
-            real_name: j["real_name"].as_str().map(String::from),
-            remain_flux: j["remain_flux"].as_u64(),
-            remain_times: j["remain_times"].as_u64(),
-            suc_msg: j["suc_msg"].as_str().map(String::from),
-            sysver: j["sysver"].as_str().map(String::from),
-            username: j["username"].as_str().map(String::from),
-            wallet_balance: j["wallet_balance"].as_u64(),
+            client_ip: optional_string(&j, "client_ip"),
+            ecode: optional_ecode(&j),
+            error: required_string(&j, "error", &s)?,
+            error_msg: optional_string(&j, "error_msg"),
+            online_ip: optional_string(&j, "online_ip"),
+            res: optional_string(&j, "res"),
+            srun_ver: optional_string(&j, "srun_ver"),
+            st: optional_u64(&j, "st"),
+            server_flag: optional_u64(&j, "ServerFlag"),
+            services_intf_server_ip: optional_string(&j, "services_intf_server_ip"),
+            services_intf_server_port: optional_string(&j, "services_intf_server_port"),
+            access_token: optional_string(&j, "access_token"),
+            checkout_date: optional_u64(&j, "checkout_date"),
+            poly_msg: optional_string(&j, "poly_msg"),
+            real_name: optional_string(&j, "real_name"),
+            remain_flux: optional_u64(&j, "remain_flux"),
+            remain_times: optional_u64(&j, "remain_times"),
+            suc_msg: optional_string(&j, "suc_msg"),
+            sysver: optional_string(&j, "sysver"),
+            username: optional_string(&j, "username"),
+            wallet_balance: optional_f64(&j, "wallet_balance"),
+            raw: s,
         })
     }
 }
 
+/// Response of `srun_portal` with `action=logout`.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct SRUNLogoutResponse {
-    pub client_ip: String,
-    pub ecode: u64,
+    pub client_ip: Option<String>,
+    pub ecode: Option<ECode>,
     pub error: String,
-    pub error_msg: String,
-    pub online_ip: String,
-    pub res: String,
-    pub srun_ver: String,
+    pub error_msg: Option<String>,
+    pub online_ip: Option<String>,
+    pub res: Option<String>,
+    pub srun_ver: Option<String>,
+
+    /// The JSON document the response was decoded from.
+    pub raw: String,
 }
 
 impl fmt::Display for SRUNLogoutResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.error == "ok" {
-            write!(f, "Logout OK\n")?;
+            writeln!(f, "Logout OK")?;
         } else {
-            write!(f, "Logout Failed: {}\n", self.error_msg)?;
+            writeln!(
+                f,
+                "Logout Failed: {}",
+                self.error_msg.as_deref().unwrap_or(&self.error)
+            )?;
+            if let Some(reason) = self.reason() {
+                writeln!(f, "{}", reason)?;
+            }
         }
         Ok(())
     }
@@ -345,23 +488,114 @@ impl SRUNResponse for SRUNLogoutResponse {
             online_ip: self.online_ip.clone(),
             res: self.res.clone(),
             srun_ver: self.srun_ver.clone(),
+            reason: self.reason().map(JsonValue::from),
         };
         json::stringify(obj)
     }
+
+    fn is_ok(&self) -> bool {
+        self.error == "ok"
+    }
 }
 
 impl SRUNLogoutResponse {
-    pub fn from_string(s: String) -> Result<SRUNLogoutResponse, Box<dyn std::error::Error>> {
-        let j = json::parse(&s)?;
+    /// Explanation of the failure from the catalog of known SRUN errors, `None` when ok.
+    pub fn reason(&self) -> Option<&'static Reason> {
+        failure_reason(&self.error, &self.ecode, &self.error_msg)
+    }
+
+    /// Decodes the JSON payload extracted from the JSONP response.
+    pub fn from_string(s: String) -> Result<SRUNLogoutResponse, SRUNClientError> {
+        let j = parse_json(&s)?;
 
         Ok(SRUNLogoutResponse {
-            client_ip: j["client_ip"].as_str().unwrap().to_string(),
-            ecode: j["ecode"].as_u64().unwrap(),
-            error: j["error"].as_str().unwrap().to_string(),
-            error_msg: j["error_msg"].as_str().unwrap().to_string(),
-            online_ip: j["online_ip"].as_str().unwrap().to_string(),
-            res: j["res"].as_str().unwrap().to_string(),
-            srun_ver: j["srun_ver"].as_str().unwrap().to_string(),
+            client_ip: optional_string(&j, "client_ip"),
+            ecode: optional_ecode(&j),
+            error: required_string(&j, "error", &s)?,
+            error_msg: optional_string(&j, "error_msg"),
+            online_ip: optional_string(&j, "online_ip"),
+            res: optional_string(&j, "res"),
+            srun_ver: optional_string(&j, "srun_ver"),
+            raw: s,
         })
     }
 }
+
+fn parse_json(s: &str) -> Result<JsonValue, SRUNClientError> {
+    let j = json::parse(s).map_err(|e| SRUNClientError::Parse {
+        message: e.to_string(),
+        body: s.to_string(),
+    })?;
+    if !j.is_object() {
+        return Err(SRUNClientError::Parse {
+            message: String::from("expected a JSON object"),
+            body: s.to_string(),
+        });
+    }
+    Ok(j)
+}
+
+fn required_string(j: &JsonValue, field: &str, raw: &str) -> Result<String, SRUNClientError> {
+    optional_string(j, field).ok_or_else(|| SRUNClientError::MissingField {
+        field: field.to_string(),
+        body: raw.to_string(),
+    })
+}
+
+// Portals disagree on whether fields are strings or numbers, so accept either encoding
+
+fn optional_string(j: &JsonValue, field: &str) -> Option<String> {
+    match &j[field] {
+        JsonValue::String(_) | JsonValue::Short(_) => j[field].as_str().map(String::from),
+        JsonValue::Number(n) => Some(n.to_string()),
+        JsonValue::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// Counters sent with a fractional part are truncated rather than dropped
+fn optional_u64(j: &JsonValue, field: &str) -> Option<u64> {
+    let exact = match &j[field] {
+        JsonValue::Number(_) => j[field].as_u64(),
+        JsonValue::String(_) | JsonValue::Short(_) => {
+            j[field].as_str().and_then(|s| s.trim().parse().ok())
+        }
+        _ => None,
+    };
+    exact.or_else(|| {
+        optional_f64(j, field)
+            .filter(|n| n.is_finite() && *n >= 0.0)
+            .map(|n| n as u64)
+    })
+}
+
+fn optional_f64(j: &JsonValue, field: &str) -> Option<f64> {
+    match &j[field] {
+        JsonValue::Number(_) => j[field].as_f64(),
+        JsonValue::String(_) | JsonValue::Short(_) => {
+            j[field].as_str().and_then(|s| s.trim().parse().ok())
+        }
+        _ => None,
+    }
+}
+
+fn optional_ecode(j: &JsonValue) -> Option<ECode> {
+    if let Some(n) = optional_u64(j, "ecode") {
+        return Some(ECode::Number(n));
+    }
+    optional_string(j, "ecode")
+        .filter(|s| !s.is_empty())
+        .map(ECode::Text)
+}
+
+fn failure_reason(
+    error: &str,
+    ecode: &Option<ECode>,
+    error_msg: &Option<String>,
+) -> Option<&'static Reason> {
+    if error == "ok" {
+        return None;
+    }
+    let ecode = ecode.as_ref().map(ECode::to_string);
+    reason::lookup(ecode.as_deref(), error, error_msg.as_deref())
+}
@@ -1,37 +1,56 @@
+use super::secret::SecretString;
 use base64::{self, Engine};
-use json::{object, stringify};
+use json::object;
+use zeroize::{Zeroize, Zeroizing};
 
-pub struct SRBX1 {
+/// Encoder for the `{SRBX1}` login `info` field. It borrows the password instead of keeping a
+/// copy of its own.
+pub struct SRBX1<'a> {
     username: String,
-    password: String,
+    password: &'a SecretString,
     ip: String,
     acid: String,
     enc_ver: String,
 }
 
-impl SRBX1 {
-    pub fn new(username: &str, password: &str, ip: &str, acid: &str) -> Self {
+impl<'a> SRBX1<'a> {
+    pub fn new(username: &str, password: &'a SecretString, ip: &str, acid: &str) -> Self {
         SRBX1 {
             username: username.to_string(),
-            password: password.to_string(),
+            password,
             ip: ip.to_string(),
             acid: acid.to_string(),
             enc_ver: String::from("srun_bx1"),
         }
     }
 
+    /// Replaces the default `srun_bx1` encoding version.
+    pub fn with_enc_ver(mut self, enc_ver: &str) -> Self {
+        self.enc_ver = enc_ver.to_string();
+        self
+    }
+
+    /// Encrypts the login info with `challenge` and returns it prefixed with `{SRBX1}`.
     pub fn calculate_auth_code(&self, challenge: &str) -> String {
-        let obj = object! {
+        let mut obj = object! {
             username: self.username.clone(),
-            password: self.password.clone(),
+            password: self.password.expose(),
             ip: self.ip.clone(),
             acid: self.acid.clone(),
             enc_ver: self.enc_ver.clone(),
         };
-        let json = stringify(obj);
+        // The plaintext copies of the password are wiped, the compressed info is encrypted in place
+        let json = Zeroizing::new(obj.dump());
+        if let Some(mut password) = obj["password"].take_string() {
+            password.zeroize();
+        }
 
         let mut info_compressed = SRBX1::compress(json.as_str(), true);
-        let challenge_compressed = SRBX1::compress(challenge, false);
+        let mut challenge_compressed = SRBX1::compress(challenge, false);
+        // The key is indexed up to 3, missing words count as 0 just like in the portal's JavaScript
+        if challenge_compressed.len() < 4 {
+            challenge_compressed.resize(4, 0);
+        }
 
         let n = info_compressed.len() - 1;
         let mut z = info_compressed[n];
@@ -44,7 +63,7 @@ impl SRBX1 {
 
         while q > 0 {
             q -= 1;
-            d = d.wrapping_add(c) & (0x8CE0D9BF | 0x731F2640);
+            d = d.wrapping_add(c);
             e = ((d >> 2) & 3) as usize;
 
             for p in 0..n {
@@ -52,7 +71,7 @@ impl SRBX1 {
                 m = (z >> 5) ^ (y << 2);
                 m = m.wrapping_add((y >> 3) ^ (z << 4) ^ (d ^ y));
                 m = m.wrapping_add(challenge_compressed[p & 3 ^ e] ^ z);
-                z = info_compressed[p].wrapping_add(m) & (0xEFB8D130 | 0x10472ECF);
+                z = info_compressed[p].wrapping_add(m);
                 info_compressed[p] = z;
             }
 
@@ -60,7 +79,7 @@ impl SRBX1 {
             m = (z >> 5) ^ (y << 2);
             m = m.wrapping_add((y >> 3) ^ (z << 4) ^ (d ^ y));
             m = m.wrapping_add(challenge_compressed[n & 3 ^ e] ^ z);
-            z = info_compressed[n].wrapping_add(m) & (0xBB390742 | 0x44C6F8BD);
+            z = info_compressed[n].wrapping_add(m);
             info_compressed[n] = z;
         }
 
@@ -76,7 +95,7 @@ impl SRBX1 {
         let custom = base64::alphabet::Alphabet::new(b64_charset).unwrap();
         let engine =
             base64::engine::GeneralPurpose::new(&custom, base64::engine::general_purpose::PAD);
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return String::from("");
         }
 
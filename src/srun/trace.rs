@@ -0,0 +1,103 @@
+use super::error::SRUNClientError;
+use super::transport::{HttpRequest, HttpResponse, Transport};
+use std::fmt::{self, Write as _};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Query parameters from which the password can be recovered or that carry it encrypted.
+pub const SECRET_PARAMS: &[&str] = &["password", "info", "chksum"];
+
+/// Where a wire trace is written. Clones share the writer, so the transports of a client and
+/// its IPv6 sibling end up in one trace.
+#[derive(Clone)]
+pub struct TraceSink {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    secrets: bool,
+}
+
+impl TraceSink {
+    pub fn new(writer: impl Write + Send + 'static) -> TraceSink {
+        TraceSink {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            secrets: false,
+        }
+    }
+
+    /// Writes the [`SECRET_PARAMS`] in the clear instead of redacting them.
+    pub fn with_secrets(mut self, secrets: bool) -> TraceSink {
+        self.secrets = secrets;
+        self
+    }
+
+    fn write(&self, trace: &str) {
+        // Tracing must not break the request it describes, so write errors are dropped
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writer.write_all(trace.as_bytes());
+        let _ = writer.flush();
+    }
+}
+
+impl fmt::Debug for TraceSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TraceSink")
+            .field("secrets", &self.secrets)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Passes requests on to another transport and writes each request with its query, and the
+/// response status, headers and body, to a [`TraceSink`].
+#[derive(Debug)]
+pub struct TraceTransport<T> {
+    inner: T,
+    sink: TraceSink,
+}
+
+impl<T: Transport> TraceTransport<T> {
+    pub fn new(inner: T, sink: TraceSink) -> TraceTransport<T> {
+        TraceTransport { inner, sink }
+    }
+}
+
+impl<T: Transport> Transport for TraceTransport<T> {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        let started = Instant::now();
+        let result = self.inner.execute(request);
+        let elapsed = started.elapsed().as_millis();
+
+        // One parameter per line and unencoded, to compare them with what the portal's page sends
+        let mut trace = format!("> GET {}\n", request.url);
+        for (key, value) in &request.query {
+            if !self.sink.secrets && SECRET_PARAMS.contains(&key.as_str()) {
+                let _ = writeln!(trace, ">   {key}=[REDACTED]");
+            } else {
+                let _ = writeln!(trace, ">   {key}={value}");
+            }
+        }
+        match &result {
+            Ok(resp) => {
+                let _ = writeln!(trace, "< {} ({} ms)", resp.status, elapsed);
+                for (name, value) in &resp.headers {
+                    let _ = writeln!(trace, "< {name}: {value}");
+                }
+                let _ = writeln!(trace, "<");
+                trace.push_str(&resp.body);
+                if !resp.body.ends_with('\n') {
+                    trace.push('\n');
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(trace, "! {} ({} ms)", e, elapsed);
+            }
+        }
+        trace.push('\n');
+        self.sink.write(&trace);
+
+        result
+    }
+
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        self.inner.refresh()
+    }
+}
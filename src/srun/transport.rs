@@ -0,0 +1,337 @@
+use super::error::SRUNClientError;
+use local_ip_address::list_afinet_netifas;
+use reqwest::Url;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A GET request issued by [`SRUNClient`](super::client::SRUNClient).
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub url: Url,
+    pub query: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    pub fn new(url: Url, query: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest {
+            url,
+            query: query
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// The parts of an HTTP response the protocol cares about.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: vec![],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Looks up a header, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Sends the HTTP requests of a [`SRUNClient`](super::client::SRUNClient).
+///
+/// Implementations must not follow redirects, the client inspects them itself.
+pub trait Transport: fmt::Debug {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError>;
+
+    /// Picks up changes of the local network, e.g. a new address on the bound interface.
+    /// Returns whether anything changed.
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        Ok(false)
+    }
+}
+
+/// The default transport, backed by a blocking reqwest client.
+///
+/// The underlying client, and with it the connection pool, is built on first use and shared by
+/// every later request. When bound to an interface, [`refresh`](Transport::refresh) rebuilds it
+/// if the address of that interface changed.
+#[derive(Debug)]
+pub struct ReqwestTransport {
+    interface: Option<String>,
+    ipv6: bool,
+    ipv6_source: Option<Ipv6Addr>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    client: Mutex<Option<BoundClient>>,
+}
+
+#[derive(Debug)]
+struct BoundClient {
+    client: reqwest::blocking::Client,
+    local_address: Option<IpAddr>,
+}
+
+impl ReqwestTransport {
+    /// Creates a transport whose requests originate from `interface` when given.
+    pub fn new(interface: Option<String>) -> ReqwestTransport {
+        ReqwestTransport {
+            interface,
+            ipv6: false,
+            ipv6_source: None,
+            connect_timeout: None,
+            timeout: None,
+            client: Mutex::new(None),
+        }
+    }
+
+    /// Creates a transport which only talks IPv6, from `source` when given, otherwise from the
+    /// first global IPv6 address of `interface`.
+    pub fn ipv6(interface: Option<String>, source: Option<Ipv6Addr>) -> ReqwestTransport {
+        let mut transport = ReqwestTransport::new(interface);
+        transport.ipv6 = true;
+        transport.ipv6_source = source;
+        transport
+    }
+
+    /// Limits the time spent connecting and the time spent on a whole request.
+    pub fn with_timeouts(
+        mut self,
+        connect_timeout: Option<Duration>,
+        timeout: Option<Duration>,
+    ) -> ReqwestTransport {
+        self.connect_timeout = connect_timeout;
+        self.timeout = timeout;
+        self
+    }
+
+    fn get_client(&self) -> Result<reqwest::blocking::Client, SRUNClientError> {
+        let mut cached = self.client.lock().unwrap();
+        if let Some(bound) = cached.as_ref() {
+            return Ok(bound.client.clone());
+        }
+        let local_address = self.find_local_address()?;
+        let bound = self.build_client(local_address)?;
+        let client = bound.client.clone();
+        *cached = Some(bound);
+        Ok(client)
+    }
+
+    fn build_client(&self, local_address: Option<IpAddr>) -> Result<BoundClient, SRUNClientError> {
+        let mut client_builder = reqwest::blocking::Client::builder();
+        client_builder = client_builder.redirect(reqwest::redirect::Policy::none());
+        // The blocking client applies a 30 seconds timeout unless told otherwise
+        client_builder = client_builder.timeout(self.timeout);
+        if let Some(connect_timeout) = self.connect_timeout {
+            client_builder = client_builder.connect_timeout(connect_timeout);
+        }
+        if let Some(ip) = local_address {
+            client_builder = client_builder.local_address(ip);
+        }
+        Ok(BoundClient {
+            client: client_builder.build()?,
+            local_address,
+        })
+    }
+
+    fn find_local_address(&self) -> Result<Option<IpAddr>, SRUNClientError> {
+        if let Some(source) = self.ipv6_source {
+            return Ok(Some(IpAddr::V6(source)));
+        }
+        // Try to find a local address under speficied interface
+        let interface = match self.interface.clone() {
+            Some(interface) => interface,
+            // Binding the unspecified address still keeps connections on IPv6
+            None if self.ipv6 => return Ok(Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))),
+            None => return Ok(None),
+        };
+        // Ordered, so the same address is picked every time the interface is looked up
+        let mut address_map: HashMap<String, BTreeSet<IpAddr>> = HashMap::new();
+        let network_interfaces = list_afinet_netifas().map_err(|e| SRUNClientError::Interface {
+            interface: interface.clone(),
+            message: format!("failed to list network interfaces: {e}"),
+        })?;
+        for (name, ip) in network_interfaces.iter() {
+            if !address_map.contains_key(name) {
+                address_map.insert(name.clone(), BTreeSet::new());
+            }
+            address_map.get_mut(name).unwrap().insert(*ip);
+        }
+
+        match address_map.get(&interface) {
+            Some(ips) if self.ipv6 => match ips.iter().find(|ip| is_global_ipv6(ip)) {
+                Some(ip) => Ok(Some(*ip)),
+                None => Err(SRUNClientError::Interface {
+                    interface,
+                    message: String::from("no global IPv6 address"),
+                }),
+            },
+            Some(ips) => {
+                // If no ipv4 address is found, use a global ipv6 address instead, a link-local
+                // one cannot reach the portal
+                let ip = ips
+                    .iter()
+                    .find(|ip| ip.is_ipv4())
+                    .or_else(|| ips.iter().find(|ip| is_global_ipv6(ip)));
+                match ip {
+                    Some(ip) => Ok(Some(*ip)),
+                    None => Err(SRUNClientError::Interface {
+                        interface,
+                        message: String::from("no IPv4 or global IPv6 address"),
+                    }),
+                }
+            }
+            None => Err(SRUNClientError::Interface {
+                interface,
+                message: String::from("not found"),
+            }),
+        }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        let client = self.get_client()?;
+        // Errors name the url without the query, which may carry the password hash
+        let resp = client
+            .get(request.url.as_str())
+            .query(&request.query)
+            .send()
+            .map_err(|e| e.with_url(request.url.clone()))?;
+        let status = resp.status().as_u16();
+        let headers = resp
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+        let body = resp.text().map_err(|e| e.with_url(request.url.clone()))?;
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        if self.interface.is_none() || self.ipv6_source.is_some() {
+            return Ok(false);
+        }
+        let local_address = self.find_local_address()?;
+        let mut cached = self.client.lock().unwrap();
+        match cached.as_ref() {
+            Some(bound) if bound.local_address == local_address => Ok(false),
+            _ => {
+                *cached = Some(self.build_client(local_address)?);
+                Ok(true)
+            }
+        }
+    }
+}
+
+// Skips loopback, link-local (fe80::/10) and unique local (fc00::/7) addresses
+fn is_global_ipv6(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !ip.is_loopback()
+                && !ip.is_unspecified()
+                && (first & 0xffc0) != 0xfe80
+                && (first & 0xfe00) != 0xfc00
+        }
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// Serves canned responses keyed by url path, for tests and offline replay.
+///
+/// Responses registered for the same path are returned in order, the last one is repeated
+/// once the others are used up. Every request is kept and can be inspected with
+/// [`requests`](MemoryTransport::requests).
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    responses: Mutex<HashMap<String, VecDeque<HttpResponse>>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> MemoryTransport {
+        MemoryTransport::default()
+    }
+
+    /// Queues `response` for requests to `path`, e.g. `/cgi-bin/rad_user_info`.
+    pub fn respond(self, path: &str, response: HttpResponse) -> MemoryTransport {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Requests received so far, in order.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let mut responses = self.responses.lock().unwrap();
+        let queue = responses
+            .get_mut(request.url.path())
+            .filter(|q| !q.is_empty());
+        match queue {
+            Some(queue) if queue.len() > 1 => Ok(queue.pop_front().unwrap()),
+            Some(queue) => Ok(queue.front().unwrap().clone()),
+            None => Err(SRUNClientError::Transport(
+                format!("no response registered for {}", request.url.path()).into(),
+            )),
+        }
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for std::sync::Arc<T> {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        (**self).execute(request)
+    }
+
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        (**self).refresh()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn execute(&self, request: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        (**self).execute(request)
+    }
+
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        (**self).refresh()
+    }
+}
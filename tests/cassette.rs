@@ -0,0 +1,111 @@
+mod common;
+
+use common::MockPortal;
+use srun_cli::{
+    Cassette, CassetteRecorder, LoginOptions, LoginOutcome, ReasonKind, ReplayTransport,
+    SRUNClient, SRUNClientCredentials, SRUNClientError, SRUNClientOptions,
+};
+use std::sync::Arc;
+
+fn fixture(name: &str) -> Cassette {
+    Cassette::load(format!(
+        "{}/tests/fixtures/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
+
+fn replay(cassette: Cassette) -> (SRUNClient, Arc<ReplayTransport>) {
+    let transport = Arc::new(ReplayTransport::new(cassette));
+    // A replayed failure would fail the same way again
+    let mut options = SRUNClientOptions::default();
+    options.retry.retries = 0;
+    let client = SRUNClient::with_transport(
+        "http://10.0.0.1",
+        Some(SRUNClientCredentials::new(
+            common::USERNAME,
+            common::PASSWORD,
+        )),
+        options,
+        Box::new(transport.clone()),
+    );
+    (client, transport)
+}
+
+#[test]
+fn recorded_session_replays_without_secrets() {
+    let portal = MockPortal::start();
+    let path = std::env::temp_dir().join(format!("srun-cli-cassette-{}.json", std::process::id()));
+    let recorder = CassetteRecorder::create(&path).unwrap();
+    let mut options = SRUNClientOptions::default();
+    options.redirect_host = portal.probe_url();
+    options.record = Some(recorder.clone());
+    let client = SRUNClient::new(
+        &portal.url(),
+        Some(SRUNClientCredentials::new(
+            common::USERNAME,
+            common::PASSWORD,
+        )),
+        options,
+    );
+    client.full_login(&LoginOptions::default()).unwrap();
+
+    let cassette = Cassette::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        cassette.exchanges.len(),
+        recorder.cassette().exchanges.len()
+    );
+    let sent = portal.last_request("/cgi-bin/srun_portal").unwrap();
+    let recorded = cassette.to_json().dump();
+    for secret in ["password", "info", "chksum"] {
+        assert!(!recorded.contains(&sent[secret]), "{secret} leaked");
+    }
+
+    let (client, transport) = replay(cassette);
+    match client.full_login(&LoginOptions::default()).unwrap() {
+        LoginOutcome::Completed { response, .. } => assert_eq!(response.error, "ok"),
+        LoginOutcome::AlreadyOnline => panic!("portal was offline"),
+    }
+    assert_eq!(transport.remaining(), 0);
+}
+
+#[test]
+fn fixture_login_completes() {
+    let (client, transport) = replay(fixture("login.json"));
+
+    match client.full_login(&LoginOptions::default()).unwrap() {
+        LoginOutcome::Completed {
+            response, ac_id, ..
+        } => {
+            assert_eq!(response.error, "ok");
+            assert_eq!(ac_id, common::AC_ID);
+        }
+        LoginOutcome::AlreadyOnline => panic!("portal was offline"),
+    }
+    assert_eq!(transport.remaining(), 0);
+}
+
+#[test]
+fn fixture_too_many_devices_is_explained() {
+    let (client, _) = replay(fixture("login_too_many_devices.json"));
+
+    let err = client.full_login(&LoginOptions::default()).unwrap_err();
+
+    assert!(matches!(err, SRUNClientError::Portal { .. }), "{err:?}");
+    assert_eq!(
+        err.reason().map(|r| r.kind),
+        Some(ReasonKind::TooManyDevices)
+    );
+}
+
+#[test]
+fn replay_runs_out_of_exchanges() {
+    let (client, _) = replay(fixture("login.json"));
+    client.query().unwrap();
+
+    assert!(matches!(
+        client.query().unwrap_err(),
+        SRUNClientError::Transport(_)
+    ));
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::MockPortal;
+use std::process::Command;
+
+fn srun_cli(portal: &MockPortal) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_srun-cli"));
+    // Keep the configuration and SRUN_ variables of the host out of the way
+    command.env_clear().args([
+        "-c",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/empty.toml"),
+        "-s",
+        &portal.url(),
+    ]);
+    command
+}
+
+#[test]
+fn logout_needs_no_password() {
+    let portal = MockPortal::start();
+    portal.set_online(true);
+
+    let output = srun_cli(&portal)
+        .args(["-u", common::USERNAME, "logout"])
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!portal.is_online());
+    let params = portal.last_request("/cgi-bin/srun_portal").unwrap();
+    assert_eq!(params["username"], common::USERNAME);
+}
@@ -0,0 +1,454 @@
+//! A minimal SRUN portal served over plain HTTP on localhost.
+//!
+//! It implements the endpoints the client talks to and independently verifies the `{MD5}`
+//! password, the `{SRBX1}` info field and `chksum` of login requests, so the tests exercise the
+//! protocol rather than comparing the client against itself.
+
+#![allow(dead_code)]
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const USERNAME: &str = "student";
+pub const PASSWORD: &str = "correct horse";
+pub const AC_ID: &str = "7";
+pub const CLIENT_IP: &str = "10.20.30.40";
+
+const SRUN_VER: &str = "SRunCGIAuthIntfSvr V1.18 B20190423";
+const B64_CHARSET: &str = "LVoJPiCN2R8G90yg+hmFHuacZ1OWMnrsSTXkYpUq/3dlbfKwv6xztjI7DeBE45QA";
+
+pub struct Request {
+    pub path: String,
+    pub params: HashMap<String, String>,
+}
+
+struct State {
+    online: bool,
+    challenge_counter: u32,
+    challenges: Vec<String>,
+    requests: Vec<Request>,
+    enc_ver: String,
+}
+
+pub struct MockPortal {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockPortal {
+    pub fn start() -> MockPortal {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State {
+            online: false,
+            challenge_counter: 0,
+            challenges: vec![],
+            requests: vec![],
+            enc_ver: String::from("srun_bx1"),
+        }));
+
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => handle(stream, &server_state),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        MockPortal { port, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn probe_url(&self) -> String {
+        format!("{}/generate_204", self.url())
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.state.lock().unwrap().online
+    }
+
+    pub fn set_online(&self, online: bool) {
+        self.state.lock().unwrap().online = online;
+    }
+
+    /// Encoding version the `{SRBX1}` info field of a login must carry.
+    pub fn set_enc_ver(&self, enc_ver: &str) {
+        self.state.lock().unwrap().enc_ver = enc_ver.to_string();
+    }
+
+    /// Paths requested so far, in order.
+    pub fn paths(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.requests.iter().map(|r| r.path.clone()).collect()
+    }
+
+    pub fn last_request(&self, path: &str) -> Option<HashMap<String, String>> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .rev()
+            .find(|r| r.path == path)
+            .map(|r| r.params.clone())
+    }
+}
+
+fn handle(stream: TcpStream, state: &Arc<Mutex<State>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Skip headers, every request we serve is a body-less GET
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) if line == "\r\n" || line == "\n" => break,
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = parse_query(query);
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(Request {
+        path: path.to_string(),
+        params: params.clone(),
+    });
+
+    let (status, headers, body) = route(path, &params, &mut state);
+    drop(state);
+
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    );
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    response.push_str(&body);
+
+    let mut stream = stream;
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn route(
+    path: &str,
+    params: &HashMap<String, String>,
+    state: &mut State,
+) -> (&'static str, Vec<(&'static str, String)>, String) {
+    let callback = params.get("callback").cloned().unwrap_or_default();
+    let jsonp = |j: json::JsonValue| format!("{}({})", callback, j.dump());
+
+    match path {
+        "/generate_204" => {
+            if state.online {
+                ("204 No Content", vec![], String::new())
+            } else {
+                (
+                    "302 Found",
+                    vec![("Location", format!("/index_1.html?ac_id={AC_ID}"))],
+                    String::new(),
+                )
+            }
+        }
+        "/index_1.html" => (
+            "302 Found",
+            vec![(
+                "Location",
+                format!("/srun_portal_pc?ac_id={AC_ID}&theme=pro"),
+            )],
+            String::new(),
+        ),
+        "/cgi-bin/rad_user_info" => {
+            let body = if state.online {
+                json::object! {
+                    ServerFlag: 0,
+                    add_time: 1700000000,
+                    all_bytes: 1024,
+                    bytes_in: 512,
+                    bytes_out: 512,
+                    error: "ok",
+                    online_device_total: "1",
+                    online_ip: CLIENT_IP,
+                    sum_bytes: "4096",
+                    user_name: USERNAME,
+                }
+            } else {
+                json::object! {
+                    client_ip: CLIENT_IP,
+                    ecode: 0,
+                    error: "not_online_error",
+                    error_msg: "",
+                    online_ip: CLIENT_IP,
+                    res: "not_online_error",
+                    srun_ver: SRUN_VER,
+                    st: 1700000000,
+                }
+            };
+            ("200 OK", vec![], jsonp(body))
+        }
+        "/cgi-bin/get_challenge" => {
+            state.challenge_counter += 1;
+            let challenge = format!(
+                "{:x}",
+                Sha1::digest(format!("challenge-{}", state.challenge_counter))
+            );
+            state.challenges.push(challenge.clone());
+            let body = json::object! {
+                challenge: challenge,
+                client_ip: CLIENT_IP,
+                ecode: 0,
+                error: "ok",
+                error_msg: "",
+                expire: "60",
+                online_ip: params.get("ip").cloned().unwrap_or_default(),
+                res: "ok",
+                srun_ver: SRUN_VER,
+                st: 1700000000,
+            };
+            ("200 OK", vec![], jsonp(body))
+        }
+        "/cgi-bin/srun_portal" => {
+            let action = params.get("action").map(String::as_str);
+            let body = match action {
+                Some("login") => match verify_login(params, state) {
+                    Ok(()) => {
+                        state.online = true;
+                        json::object! {
+                            ServerFlag: 0,
+                            ac_id: AC_ID,
+                            client_ip: CLIENT_IP,
+                            ecode: 0,
+                            error: "ok",
+                            error_msg: "",
+                            online_ip: CLIENT_IP,
+                            res: "ok",
+                            srun_ver: SRUN_VER,
+                            suc_msg: "login_ok",
+                            username: USERNAME,
+                        }
+                    }
+                    Err((ecode, error, error_msg)) => json::object! {
+                        client_ip: CLIENT_IP,
+                        ecode: ecode,
+                        error: error,
+                        error_msg: error_msg,
+                        online_ip: CLIENT_IP,
+                        res: error,
+                        srun_ver: SRUN_VER,
+                        st: 1700000000,
+                    },
+                },
+                Some("logout") => {
+                    if params.get("username").map(String::as_str) == Some(USERNAME)
+                        && params.get("ac_id").map(String::as_str) == Some(AC_ID)
+                    {
+                        state.online = false;
+                        json::object! {
+                            client_ip: CLIENT_IP,
+                            ecode: 0,
+                            error: "ok",
+                            error_msg: "",
+                            online_ip: CLIENT_IP,
+                            res: "ok",
+                            srun_ver: SRUN_VER,
+                        }
+                    } else {
+                        json::object! {
+                            client_ip: CLIENT_IP,
+                            ecode: "E2833",
+                            error: "logout_error",
+                            error_msg: "E2833: Your IP address is not online",
+                            online_ip: CLIENT_IP,
+                            res: "logout_error",
+                            srun_ver: SRUN_VER,
+                        }
+                    }
+                }
+                _ => return ("400 Bad Request", vec![], String::new()),
+            };
+            ("200 OK", vec![], jsonp(body))
+        }
+        _ => ("404 Not Found", vec![], String::new()),
+    }
+}
+
+fn verify_login(
+    params: &HashMap<String, String>,
+    state: &State,
+) -> Result<(), (&'static str, &'static str, &'static str)> {
+    let get = |k: &str| params.get(k).cloned().unwrap_or_default();
+    let sign_error = ("E2553", "sign_error", "checksum mismatch");
+
+    let challenge = match state.challenges.last() {
+        Some(c) => c.clone(),
+        None => return Err(("E2532", "challenge_expire_error", "no challenge issued")),
+    };
+    let username = get("username");
+    let ip = get("ip");
+    let ac_id = get("ac_id");
+    let info = get("info");
+    let n = get("n");
+    let ty = get("type");
+
+    if ac_id != AC_ID {
+        return Err(("E2500", "login_error", "wrong ac_id"));
+    }
+
+    let hmac = match get("password").strip_prefix("{MD5}") {
+        Some(h) => h.to_string(),
+        None => return Err(sign_error),
+    };
+
+    let checksum = format!(
+        "{:x}",
+        Sha1::digest(
+            [
+                &challenge, &username, &challenge, &hmac, &challenge, &ac_id, &challenge, &ip,
+                &challenge, &n, &challenge, &ty, &challenge, &info,
+            ]
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<String>()
+        )
+    );
+    if checksum != get("chksum") {
+        return Err(sign_error);
+    }
+
+    let decoded = match decode_info(&info, &challenge) {
+        Some(d) => d,
+        None => return Err(sign_error),
+    };
+    if decoded["username"] != username.as_str()
+        || decoded["ip"] != ip.as_str()
+        || decoded["acid"] != ac_id.as_str()
+        || decoded["enc_ver"] != state.enc_ver.as_str()
+    {
+        return Err(sign_error);
+    }
+
+    let mut mac = Hmac::<Md5>::new_from_slice(challenge.as_bytes()).unwrap();
+    mac.update(PASSWORD.as_bytes());
+    let expected_hmac = format!("{:x}", mac.finalize().into_bytes());
+    if username != USERNAME || hmac != expected_hmac || decoded["password"] != PASSWORD {
+        return Err((
+            "E2901",
+            "login_error",
+            "E2901: (Third party 1)bind user failed",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reverses the `{SRBX1}` encoding: custom base64, then the XXTEA variant used by the portal.
+fn decode_info(info: &str, challenge: &str) -> Option<json::JsonValue> {
+    let alphabet = base64::alphabet::Alphabet::new(B64_CHARSET).unwrap();
+    let engine =
+        base64::engine::GeneralPurpose::new(&alphabet, base64::engine::general_purpose::PAD);
+    let bytes = engine.decode(info.strip_prefix("{SRBX1}")?).ok()?;
+    if bytes.len() % 4 != 0 || bytes.len() < 8 {
+        return None;
+    }
+
+    let mut v: Vec<u32> = bytes
+        .chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    let mut k: Vec<u32> = challenge
+        .as_bytes()
+        .chunks(4)
+        .map(|c| {
+            c.iter()
+                .enumerate()
+                .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (i * 8))
+        })
+        .collect();
+    k.resize(k.len().max(4), 0);
+
+    let delta: u32 = 0x9E3779B9;
+    let n = v.len() - 1;
+    let rounds = 6 + 52 / (n + 1);
+    let mut d = delta.wrapping_mul(rounds as u32);
+    let mx = |z: u32, y: u32, d: u32, p: usize, e: usize| -> u32 {
+        ((z >> 5) ^ (y << 2))
+            .wrapping_add((y >> 3) ^ (z << 4) ^ (d ^ y))
+            .wrapping_add(k[(p & 3) ^ e] ^ z)
+    };
+
+    let mut y = v[0];
+    for _ in 0..rounds {
+        let e = ((d >> 2) & 3) as usize;
+        for p in (1..=n).rev() {
+            let z = v[p - 1];
+            v[p] = v[p].wrapping_sub(mx(z, y, d, p, e));
+            y = v[p];
+        }
+        let z = v[n];
+        v[0] = v[0].wrapping_sub(mx(z, y, d, 0, e));
+        y = v[0];
+        d = d.wrapping_sub(delta);
+    }
+
+    let len = v[n] as usize;
+    let plain: Vec<u8> = v[..n].iter().flat_map(|w| w.to_le_bytes()).collect();
+    if len > plain.len() {
+        return None;
+    }
+    json::parse(std::str::from_utf8(&plain[..len]).ok()?).ok()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,93 @@
+use srun_cli::srun::connectivity::{ConnectivityChecker, Probe, Quorum};
+use srun_cli::{HttpResponse, MemoryTransport};
+use std::net::TcpListener;
+
+fn probes(specs: &[&str]) -> Vec<Probe> {
+    specs.iter().map(|s| s.parse().unwrap()).collect()
+}
+
+#[test]
+fn probes_parse_from_strings() {
+    assert_eq!(
+        "http:http://probe.test/generate_204".parse::<Probe>(),
+        Ok(Probe::HttpStatus {
+            url: String::from("http://probe.test/generate_204"),
+            status: 204,
+        })
+    );
+    assert_eq!(
+        "http:200:http://probe.test/".parse::<Probe>(),
+        Ok(Probe::HttpStatus {
+            url: String::from("http://probe.test/"),
+            status: 200,
+        })
+    );
+    assert_eq!(
+        "body:http://probe.test/hotspot.html Success".parse::<Probe>(),
+        Ok(Probe::HttpBody {
+            url: String::from("http://probe.test/hotspot.html"),
+            contains: String::from("Success"),
+        })
+    );
+    assert!("tcp:1.1.1.1".parse::<Probe>().is_err());
+    assert!("ping:1.1.1.1".parse::<Probe>().is_err());
+    assert_eq!("2".parse::<Quorum>(), Ok(Quorum::AtLeast(2)));
+    assert!("most".parse::<Quorum>().is_err());
+}
+
+#[test]
+fn quorum_tolerates_blocked_probe_host() {
+    let transport = MemoryTransport::new()
+        .respond("/generate_204", HttpResponse::new(204, ""))
+        .respond(
+            "/hotspot.html",
+            HttpResponse::new(200, "<HTML><BODY>Success</BODY></HTML>"),
+        );
+    // No response is registered for the third host, as if it were blocked
+    let specs = probes(&[
+        "http:http://a.test/generate_204",
+        "body:http://b.test/hotspot.html Success",
+        "http:http://c.test/blocked",
+    ]);
+
+    let report = ConnectivityChecker::new(specs.clone(), Quorum::Majority).check(&transport);
+    assert!(report.online);
+    assert!(report.results[2].result.is_err());
+
+    let report = ConnectivityChecker::new(specs, Quorum::All).check(&transport);
+    assert!(!report.online);
+}
+
+#[test]
+fn quorum_must_be_reachable_by_probes() {
+    assert!(Quorum::AtLeast(2).validate(2).is_ok());
+    assert!(Quorum::AtLeast(3).validate(2).is_err());
+    assert!(Quorum::AtLeast(0).validate(2).is_err());
+    assert!(Quorum::All.validate(1).is_ok());
+}
+
+#[test]
+fn hijacked_probe_counts_as_offline() {
+    let transport = MemoryTransport::new().respond(
+        "/generate_204",
+        HttpResponse::new(302, "").with_header("Location", "http://portal.test/"),
+    );
+
+    let report =
+        ConnectivityChecker::new(probes(&["http:http://a.test/generate_204"]), Quorum::Any)
+            .check(&transport);
+
+    assert!(!report.online);
+    assert!(matches!(report.results[0].result, Ok(false)));
+}
+
+#[test]
+fn dns_and_tcp_probes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let specs = probes(&["dns:localhost", &format!("tcp:127.0.0.1:{port}")]);
+
+    let report = ConnectivityChecker::new(specs, Quorum::All).check(&MemoryTransport::new());
+
+    assert!(report.online, "{report}");
+}
@@ -0,0 +1,225 @@
+mod common;
+
+use common::MockPortal;
+use srun_cli::srun::discovery::discover_portal;
+use srun_cli::{
+    LoginOptions, LoginOutcome, PortalProfile, ReasonKind, ReqwestTransport, SRUNClient,
+    SRUNClientCredentials, SRUNClientError, SRUNClientOptions, TraceSink, Transport,
+};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+fn client(portal: &MockPortal, password: &str) -> SRUNClient {
+    let mut options = SRUNClientOptions::default();
+    options.redirect_host = portal.probe_url();
+    SRUNClient::new(
+        &portal.url(),
+        Some(SRUNClientCredentials::new(common::USERNAME, password)),
+        options,
+    )
+}
+
+#[test]
+fn query_reports_offline_session() {
+    let portal = MockPortal::start();
+    let r = client(&portal, common::PASSWORD).query().unwrap();
+
+    assert_eq!(r.error, "not_online_error");
+    assert_eq!(r.online_ip, common::CLIENT_IP);
+}
+
+#[test]
+fn get_ac_id_follows_portal_redirect() {
+    let portal = MockPortal::start();
+    let ac_id = client(&portal, common::PASSWORD).get_ac_id().unwrap();
+
+    assert_eq!(ac_id, common::AC_ID);
+}
+
+#[test]
+fn full_login_authenticates_session() {
+    let portal = MockPortal::start();
+    let client = client(&portal, common::PASSWORD);
+
+    match client.full_login(&LoginOptions::default()).unwrap() {
+        LoginOutcome::Completed { response, .. } => assert_eq!(response.error, "ok"),
+        LoginOutcome::AlreadyOnline => panic!("portal was offline"),
+    }
+    assert!(portal.is_online());
+    assert_eq!(
+        portal.paths(),
+        vec![
+            "/cgi-bin/rad_user_info",
+            "/index_1.html",
+            "/cgi-bin/get_challenge",
+            "/cgi-bin/srun_portal",
+        ]
+    );
+
+    let login = portal.last_request("/cgi-bin/srun_portal").unwrap();
+    assert_eq!(login["ip"], common::CLIENT_IP);
+    assert_eq!(login["ac_id"], common::AC_ID);
+
+    let r = client.query().unwrap();
+    assert_eq!(r.error, "ok");
+    assert_eq!(r.sum_bytes, Some(4096));
+}
+
+#[test]
+fn full_login_surfaces_rejected_credentials() {
+    let portal = MockPortal::start();
+    let err = client(&portal, "wrong password")
+        .full_login(&LoginOptions::default())
+        .unwrap_err();
+
+    assert_eq!(
+        err.reason().map(|r| r.kind),
+        Some(ReasonKind::BadCredentials)
+    );
+    match err {
+        SRUNClientError::Portal { ecode, error, .. } => {
+            assert_eq!(error, "login_error");
+            assert_eq!(ecode.as_deref(), Some("E2901"));
+        }
+        e => panic!("unexpected error {e:?}"),
+    }
+    assert!(!portal.is_online());
+}
+
+#[test]
+fn full_login_aborts_when_probe_is_online() {
+    let portal = MockPortal::start();
+    portal.set_online(true);
+
+    let mut options = LoginOptions::default();
+    options.abort_if_online = true;
+    let outcome = client(&portal, common::PASSWORD)
+        .full_login(&options)
+        .unwrap();
+
+    assert!(matches!(outcome, LoginOutcome::AlreadyOnline));
+    assert_eq!(portal.paths(), vec!["/generate_204"]);
+}
+
+#[test]
+fn full_logout_ends_session() {
+    let portal = MockPortal::start();
+    portal.set_online(true);
+
+    let r = client(&portal, common::PASSWORD).full_logout().unwrap();
+
+    assert_eq!(r.response.error, "ok");
+    assert!(r.ipv6_response.is_none());
+    assert!(!portal.is_online());
+}
+
+#[test]
+fn full_login_signs_with_portal_profile() {
+    let portal = MockPortal::start();
+    portal.set_enc_ver("srun_bx2");
+
+    let mut profile = PortalProfile::default();
+    profile.enc_ver = String::from("srun_bx2");
+    profile.n = String::from("100");
+    profile.login_type = String::from("3");
+    profile.os = String::from("Linux");
+    profile.name = String::from("Linux");
+    profile.callback = String::from("jQuery1124");
+    let mut options = SRUNClientOptions::default();
+    options.portal = profile;
+    let client = SRUNClient::new(
+        &portal.url(),
+        Some(SRUNClientCredentials::new(
+            common::USERNAME,
+            common::PASSWORD,
+        )),
+        options,
+    );
+
+    client.full_login(&LoginOptions::default()).unwrap();
+
+    let login = portal.last_request("/cgi-bin/srun_portal").unwrap();
+    assert_eq!(login["n"], "100");
+    assert_eq!(login["type"], "3");
+    assert_eq!(login["os"], "Linux");
+    assert_eq!(login["callback"], "jQuery1124");
+    assert!(portal.is_online());
+}
+
+#[test]
+fn discover_portal_follows_probe_hijack() {
+    let portal = MockPortal::start();
+    let transport = ReqwestTransport::new(None);
+
+    let found = discover_portal(&transport, &portal.probe_url()).unwrap();
+    assert_eq!(found.server, format!("{}/", portal.url()));
+    assert_eq!(found.ac_id.as_deref(), Some(common::AC_ID));
+
+    portal.set_online(true);
+    match discover_portal(&transport, &portal.probe_url()).unwrap_err() {
+        SRUNClientError::PortalNotFound { online, .. } => assert!(online),
+        e => panic!("unexpected error {e:?}"),
+    }
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn trace_redacts_login_secrets() {
+    let portal = MockPortal::start();
+    let buffer = SharedBuffer::default();
+    let mut options = SRUNClientOptions::default();
+    options.redirect_host = portal.probe_url();
+    options.trace = Some(TraceSink::new(buffer.clone()));
+    let client = SRUNClient::new(
+        &portal.url(),
+        Some(SRUNClientCredentials::new(
+            common::USERNAME,
+            common::PASSWORD,
+        )),
+        options,
+    );
+
+    client.full_login(&LoginOptions::default()).unwrap();
+
+    let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let login = portal.last_request("/cgi-bin/srun_portal").unwrap();
+    assert!(trace.contains(&format!("> GET {}/cgi-bin/srun_portal", portal.url())));
+    assert!(trace.contains(">   password=[REDACTED]"));
+    assert!(trace.contains(&format!(">   username={}", common::USERNAME)));
+    assert!(trace.contains("< 200"));
+    for secret in ["password", "info", "chksum"] {
+        assert!(!trace.contains(&login[secret]), "{secret} leaked");
+    }
+}
+
+#[test]
+fn prepare_login_builds_request_without_sending() {
+    let portal = MockPortal::start();
+    let client = client(&portal, common::PASSWORD);
+
+    let plan = client.prepare_login().unwrap();
+
+    assert!(!portal
+        .paths()
+        .contains(&String::from("/cgi-bin/srun_portal")));
+    assert!(!portal.is_online());
+    assert_eq!(plan.ac_id, common::AC_ID);
+    assert_eq!(plan.ip, common::CLIENT_IP);
+
+    // The portal accepts the request as planned
+    let resp = ReqwestTransport::new(None).execute(&plan.request).unwrap();
+    assert_eq!(resp.status, 200);
+    assert!(portal.is_online());
+}
@@ -0,0 +1,25 @@
+use srun_cli::{SRUNLoginResponse, SRUNQueryResponse};
+
+#[test]
+fn fractional_numbers_are_kept() {
+    let resp = SRUNQueryResponse::from_string(String::from(
+        r#"{"error":"ok","online_ip":"10.0.0.2","sum_seconds":12.5,"bytes_in":"2048.75","user_balance":12.5,"user_charge":"3.20"}"#,
+    ))
+    .unwrap();
+
+    assert_eq!(resp.sum_seconds, Some(12));
+    assert_eq!(resp.bytes_in, Some(2048));
+    assert_eq!(resp.user_balance, Some(12.5));
+    assert_eq!(resp.user_charge, Some(3.2));
+}
+
+#[test]
+fn fractional_wallet_balance_is_kept() {
+    let resp = SRUNLoginResponse::from_string(String::from(
+        r#"{"error":"ok","online_ip":"10.0.0.2","wallet_balance":0.35,"remain_flux":-1.5}"#,
+    ))
+    .unwrap();
+
+    assert_eq!(resp.wallet_balance, Some(0.35));
+    assert_eq!(resp.remain_flux, None);
+}
@@ -0,0 +1,26 @@
+use srun_cli::{SRUNClient, SRUNClientCredentials, SRUNClientOptions, SecretString};
+
+const PASSWORD: &str = "hunter2-campus";
+
+#[test]
+fn secret_string_is_redacted() {
+    let secret = SecretString::from(PASSWORD);
+
+    assert_eq!(secret.expose(), PASSWORD);
+    assert!(!format!("{secret:?}").contains(PASSWORD));
+    assert!(!format!("{secret}").contains(PASSWORD));
+}
+
+#[test]
+fn debug_output_leaks_no_password() {
+    let credentials = SRUNClientCredentials::new("user", PASSWORD);
+    let client = SRUNClient::new(
+        "http://10.0.0.1",
+        Some(credentials.clone()),
+        SRUNClientOptions::default(),
+    );
+
+    assert!(!format!("{credentials:?}").contains(PASSWORD));
+    assert!(!format!("{client:?}").contains(PASSWORD));
+    assert!(!format!("{:?}", client.credentials()).contains(PASSWORD));
+}
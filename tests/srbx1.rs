@@ -0,0 +1,15 @@
+use srun_cli::{SecretString, SRBX1};
+
+#[test]
+fn short_challenge_is_padded_with_zero_words() {
+    // Shorter than the four words of key the encoder reads, which used to index out of bounds
+    let password = SecretString::from("password");
+    let srbx1 = SRBX1::new("user", &password, "10.0.0.2", "1");
+    let auth_code = srbx1.calculate_auth_code("abc");
+
+    assert!(auth_code.starts_with("{SRBX1}"));
+    assert_eq!(
+        auth_code,
+        srbx1.calculate_auth_code("abc\0\0\0\0\0\0\0\0\0\0\0\0\0")
+    );
+}
@@ -0,0 +1,570 @@
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use srun_cli::srun::discovery::discover_portal;
+use srun_cli::{
+    AcIdSource, HttpRequest, HttpResponse, LoginOptions, LoginOutcome, MemoryTransport, ReasonKind,
+    SRUNClient, SRUNClientCredentials, SRUNClientError, SRUNClientOptions, SRUNResponse, Transport,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CHALLENGE: &str = "4fa4ac9b0e5e9a2a2e1c6c1f6d8f4b5c0a9d1e2f3a4b5c6d7e8f9a0b1c2d3e4f";
+
+fn client_with_retries(transport: &Arc<MemoryTransport>, retries: u32) -> SRUNClient {
+    let mut options = SRUNClientOptions::default();
+    options.retry.retries = retries;
+    options.retry.backoff = Duration::ZERO;
+    SRUNClient::with_transport(
+        "http://portal.test",
+        Some(SRUNClientCredentials::new("student", "secret")),
+        options,
+        Box::new(transport.clone()),
+    )
+}
+
+fn client(transport: &Arc<MemoryTransport>) -> SRUNClient {
+    client_with_retries(transport, 0)
+}
+
+fn param(transport: &MemoryTransport, index: usize, name: &str) -> String {
+    let requests = transport.requests();
+    requests[index]
+        .query
+        .iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.clone())
+        .unwrap()
+}
+
+#[test]
+fn query_decodes_jsonp_response() {
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/cgi-bin/rad_user_info",
+        HttpResponse::new(
+            200,
+            r#"cb({"error":"not_online_error","online_ip":"10.1.2.3","ecode":"E2531"})"#,
+        ),
+    ));
+
+    let r = client(&transport).query().unwrap();
+
+    assert_eq!(r.error, "not_online_error");
+    assert_eq!(r.online_ip, "10.1.2.3");
+    assert_eq!(r.ecode.unwrap().to_string(), "E2531");
+    assert_eq!(
+        transport.requests()[0].url.as_str(),
+        "http://portal.test/cgi-bin/rad_user_info"
+    );
+}
+
+#[test]
+fn endpoints_are_relative_to_server_path() {
+    let transport = Arc::new(
+        MemoryTransport::new()
+            .respond(
+                "/srun/cgi-bin/rad_user_info",
+                HttpResponse::new(200, r#"cb({"error":"ok","online_ip":"10.1.2.3"})"#),
+            )
+            .respond(
+                "/auth/rad_user_info",
+                HttpResponse::new(200, r#"cb({"error":"ok","online_ip":"10.1.2.3"})"#),
+            ),
+    );
+
+    let mut options = SRUNClientOptions::default();
+    options.retry.retries = 0;
+    let prefixed = SRUNClient::with_transport(
+        "https://gw.example.edu/srun",
+        None,
+        options.clone(),
+        Box::new(transport.clone()),
+    );
+    prefixed.query().unwrap();
+
+    options.endpoints.rad_user_info = String::from("/auth/rad_user_info");
+    let overridden = SRUNClient::with_transport(
+        "https://gw.example.edu/srun/",
+        None,
+        options,
+        Box::new(transport.clone()),
+    );
+    overridden.query().unwrap();
+
+    let urls: Vec<String> = transport
+        .requests()
+        .iter()
+        .map(|r| r.url.to_string())
+        .collect();
+    assert_eq!(
+        urls,
+        vec![
+            "https://gw.example.edu/srun/cgi-bin/rad_user_info",
+            "https://gw.example.edu/auth/rad_user_info",
+        ]
+    );
+}
+
+#[test]
+fn query_rejects_unexpected_responses() {
+    let transport = Arc::new(
+        MemoryTransport::new()
+            .respond("/cgi-bin/rad_user_info", HttpResponse::new(502, ""))
+            .respond("/cgi-bin/rad_user_info", HttpResponse::new(200, "<html>")),
+    );
+    let client = client(&transport);
+
+    assert!(matches!(
+        client.query(),
+        Err(SRUNClientError::Http { status: 502 })
+    ));
+    assert!(matches!(client.query(), Err(SRUNClientError::Parse { .. })));
+}
+
+#[test]
+fn query_retries_server_errors() {
+    let transport = Arc::new(
+        MemoryTransport::new()
+            .respond("/cgi-bin/rad_user_info", HttpResponse::new(503, ""))
+            .respond(
+                "/cgi-bin/rad_user_info",
+                HttpResponse::new(200, r#"cb({"error":"ok","online_ip":"10.1.2.3"})"#),
+            ),
+    );
+
+    let r = client_with_retries(&transport, 1).query().unwrap();
+
+    assert_eq!(r.error, "ok");
+    assert_eq!(transport.requests().len(), 2);
+}
+
+#[test]
+fn full_login_fetches_new_challenge_when_retrying() {
+    let transport = Arc::new(
+        MemoryTransport::new()
+            .respond(
+                "/cgi-bin/rad_user_info",
+                HttpResponse::new(
+                    200,
+                    r#"cb({"error":"not_online_error","online_ip":"10.1.2.3"})"#,
+                ),
+            )
+            .respond(
+                "/index_1.html",
+                HttpResponse::new(302, "").with_header("Location", "/srun_portal_pc?ac_id=12"),
+            )
+            .respond(
+                "/cgi-bin/get_challenge",
+                HttpResponse::new(200, r#"cb({"challenge":"first","error":"ok"})"#),
+            )
+            .respond(
+                "/cgi-bin/get_challenge",
+                HttpResponse::new(200, r#"cb({"challenge":"second","error":"ok"})"#),
+            )
+            .respond(
+                "/cgi-bin/srun_portal",
+                HttpResponse::new(
+                    200,
+                    r#"cb({"error":"challenge_expire_error","ecode":"E2532"})"#,
+                ),
+            )
+            .respond(
+                "/cgi-bin/srun_portal",
+                HttpResponse::new(200, r#"cb({"error":"ok"})"#),
+            ),
+    );
+
+    let outcome = client_with_retries(&transport, 1)
+        .full_login(&LoginOptions::default())
+        .unwrap();
+
+    assert!(matches!(outcome, LoginOutcome::Completed { .. }));
+    let paths: Vec<String> = transport
+        .requests()
+        .iter()
+        .map(|r| r.url.path().to_string())
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            "/cgi-bin/rad_user_info",
+            "/index_1.html",
+            "/cgi-bin/get_challenge",
+            "/cgi-bin/srun_portal",
+            "/cgi-bin/get_challenge",
+            "/cgi-bin/srun_portal",
+        ]
+    );
+}
+
+#[test]
+fn get_ac_id_reads_location_header() {
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/index_1.html",
+        HttpResponse::new(302, "").with_header("location", "/srun_portal_pc?ac_id=12&theme=pro"),
+    ));
+
+    assert_eq!(client(&transport).get_ac_id().unwrap(), "12");
+}
+
+#[test]
+fn discover_ac_id_reads_html_redirect() {
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/index_1.html",
+        HttpResponse::new(
+            200,
+            r#"<html><head><meta http-equiv="refresh" content="0;url=/srun_portal_pc?ac_id=5&amp;theme=pro"></head></html>"#,
+        ),
+    ));
+
+    assert_eq!(
+        client(&transport).discover_ac_id().unwrap(),
+        (String::from("5"), AcIdSource::HtmlRedirect)
+    );
+
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/index_1.html",
+        HttpResponse::new(
+            200,
+            r#"<script>window.location.href = "http://portal.test/srun_portal_pc?ac_id=9";</script>"#,
+        ),
+    ));
+
+    assert_eq!(
+        client(&transport).discover_ac_id().unwrap(),
+        (String::from("9"), AcIdSource::HtmlRedirect)
+    );
+}
+
+#[test]
+fn discover_ac_id_falls_back_to_probe_and_default() {
+    let transport = Arc::new(
+        MemoryTransport::new()
+            .respond("/index_1.html", HttpResponse::new(404, ""))
+            .respond(
+                "/generate_204",
+                HttpResponse::new(302, "")
+                    .with_header("Location", "http://portal.test/index_1.html?ac_id=3"),
+            ),
+    );
+    assert_eq!(
+        client(&transport).discover_ac_id().unwrap(),
+        (String::from("3"), AcIdSource::ProbeRedirect)
+    );
+
+    let transport = Arc::new(
+        MemoryTransport::new()
+            .respond("/index_1.html", HttpResponse::new(200, "<html></html>"))
+            .respond("/generate_204", HttpResponse::new(204, "")),
+    );
+    let mut options = SRUNClientOptions::default();
+    options.retry.retries = 0;
+    options.default_ac_id = Some(String::from("1"));
+    let client = SRUNClient::with_transport(
+        "http://portal.test",
+        None,
+        options,
+        Box::new(transport.clone()),
+    );
+    assert_eq!(
+        client.discover_ac_id().unwrap(),
+        (String::from("1"), AcIdSource::Default)
+    );
+}
+
+#[test]
+fn discover_ac_id_reports_portal_discovery() {
+    let mut options = SRUNClientOptions::default();
+    options.discovered_ac_id = Some(String::from("4"));
+    let client = SRUNClient::with_transport(
+        "http://portal.test",
+        None,
+        options.clone(),
+        Box::new(MemoryTransport::new()),
+    );
+    assert_eq!(
+        client.discover_ac_id().unwrap(),
+        (String::from("4"), AcIdSource::Discovered)
+    );
+
+    // An explicit ac_id still wins over the discovered one
+    options.ac_id = Some(String::from("2"));
+    let client = SRUNClient::with_transport(
+        "http://portal.test",
+        None,
+        options,
+        Box::new(MemoryTransport::new()),
+    );
+    assert_eq!(
+        client.discover_ac_id().unwrap(),
+        (String::from("2"), AcIdSource::Configured)
+    );
+}
+
+#[test]
+fn discover_ac_id_falls_back_to_default_when_index_fails() {
+    let mut options = SRUNClientOptions::default();
+    options.retry.retries = 0;
+    options.default_ac_id = Some(String::from("1"));
+
+    // Neither the index page nor the probe answer at all
+    let client = SRUNClient::with_transport(
+        "http://portal.test",
+        None,
+        options.clone(),
+        Box::new(MemoryTransport::new()),
+    );
+    assert_eq!(
+        client.discover_ac_id().unwrap(),
+        (String::from("1"), AcIdSource::Default)
+    );
+
+    // An error page is no redirect, even if it links to the portal
+    let transport = MemoryTransport::new()
+        .respond(
+            "/index_1.html",
+            HttpResponse::new(500, r#"<a href="/srun_portal_pc?ac_id=7">retry</a>"#)
+                .with_header("Location", "/srun_portal_pc?ac_id=7"),
+        )
+        .respond("/generate_204", HttpResponse::new(204, ""));
+    let client =
+        SRUNClient::with_transport("http://portal.test", None, options, Box::new(transport));
+    assert_eq!(
+        client.discover_ac_id().unwrap(),
+        (String::from("1"), AcIdSource::Default)
+    );
+}
+
+#[test]
+fn discover_portal_keeps_path_prefix() {
+    let transport = MemoryTransport::new()
+        .respond(
+            "/generate_204",
+            HttpResponse::new(302, "").with_header("Location", "http://gw.test/srun/go.html"),
+        )
+        .respond(
+            "/srun/go.html",
+            HttpResponse::new(
+                200,
+                r#"<script>location.replace("srun_portal_pc?ac_id=4&theme=pro")</script>"#,
+            ),
+        );
+
+    let found = discover_portal(&transport, "http://probe.test/generate_204").unwrap();
+
+    assert_eq!(found.server, "http://gw.test/srun/");
+    assert_eq!(found.ac_id.as_deref(), Some("4"));
+    assert_eq!(
+        found.location,
+        "http://gw.test/srun/srun_portal_pc?ac_id=4&theme=pro"
+    );
+}
+
+#[test]
+fn login_sends_signed_parameters() {
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/cgi-bin/srun_portal",
+        HttpResponse::new(200, r#"cb({"error":"ok","online_ip":"10.1.2.3"})"#),
+    ));
+
+    let r = client(&transport)
+        .login(CHALLENGE, "10.1.2.3", "12")
+        .unwrap();
+    assert_eq!(r.error, "ok");
+
+    let mut mac = Hmac::<Md5>::new_from_slice(CHALLENGE.as_bytes()).unwrap();
+    mac.update(b"secret");
+    let hmac = format!("{:x}", mac.finalize().into_bytes());
+
+    assert_eq!(param(&transport, 0, "action"), "login");
+    assert_eq!(param(&transport, 0, "password"), format!("{{MD5}}{hmac}"));
+    assert!(param(&transport, 0, "info").starts_with("{SRBX1}"));
+    assert_eq!(param(&transport, 0, "ac_id"), "12");
+    assert_eq!(param(&transport, 0, "ip"), "10.1.2.3");
+    assert_eq!(param(&transport, 0, "chksum").len(), 40);
+}
+
+#[test]
+fn login_failure_carries_reason() {
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/cgi-bin/srun_portal",
+        HttpResponse::new(
+            200,
+            r#"cb({"error":"login_error","ecode":"E2620","error_msg":"E2620: You are already online."})"#,
+        ),
+    ));
+
+    let r = client(&transport)
+        .login(CHALLENGE, "10.1.2.3", "12")
+        .unwrap();
+
+    assert_eq!(r.reason().unwrap().kind, ReasonKind::TooManyDevices);
+    let json = json::parse(&r.to_json()).unwrap();
+    assert_eq!(json["reason"]["kind"], "too_many_devices");
+    assert!(r.to_string().contains("Log out another device"));
+}
+
+#[test]
+fn logout_sends_username_and_ip() {
+    let transport = Arc::new(MemoryTransport::new().respond(
+        "/cgi-bin/srun_portal",
+        HttpResponse::new(200, r#"cb({"error":"ok"})"#),
+    ));
+
+    let r = client(&transport).logout("10.1.2.3", "12").unwrap();
+
+    assert_eq!(r.error, "ok");
+    assert_eq!(param(&transport, 0, "action"), "logout");
+    assert_eq!(param(&transport, 0, "username"), "student");
+    assert_eq!(param(&transport, 0, "ip"), "10.1.2.3");
+}
+
+fn offline_portal(ip: &str) -> MemoryTransport {
+    MemoryTransport::new()
+        .respond(
+            "/cgi-bin/rad_user_info",
+            HttpResponse::new(
+                200,
+                &format!(r#"cb({{"error":"not_online_error","online_ip":"{ip}"}})"#),
+            ),
+        )
+        .respond(
+            "/index_1.html",
+            HttpResponse::new(302, "").with_header("Location", "/srun_portal_pc?ac_id=12"),
+        )
+        .respond(
+            "/cgi-bin/get_challenge",
+            HttpResponse::new(200, r#"cb({"challenge":"first","error":"ok"})"#),
+        )
+        .respond(
+            "/cgi-bin/srun_portal",
+            HttpResponse::new(200, &format!(r#"cb({{"error":"ok","online_ip":"{ip}"}})"#)),
+        )
+}
+
+fn dual_stack_client<T: Transport + 'static, U: Transport + 'static>(
+    transport: T,
+    transport6: U,
+) -> SRUNClient {
+    let mut options = SRUNClientOptions::default();
+    options.retry.retries = 0;
+    options.portal.double_stack = true;
+    let credentials = SRUNClientCredentials::new("student", "secret");
+    let ipv6 = SRUNClient::with_transport(
+        "http://portal.test",
+        Some(credentials.clone()),
+        options.clone(),
+        Box::new(transport6),
+    );
+    SRUNClient::with_transport(
+        "http://portal.test",
+        Some(credentials),
+        options,
+        Box::new(transport),
+    )
+    .with_ipv6_client(ipv6)
+}
+
+#[test]
+fn full_login_authenticates_ipv6_session() {
+    let transport = Arc::new(offline_portal("10.1.2.3"));
+    let transport6 = Arc::new(offline_portal("2001:db8::3"));
+    let client = dual_stack_client(transport.clone(), transport6.clone());
+
+    match client.full_login(&LoginOptions::default()).unwrap() {
+        LoginOutcome::Completed { ipv6_response, .. } => {
+            assert_eq!(
+                ipv6_response.unwrap().unwrap().online_ip.unwrap(),
+                "2001:db8::3"
+            );
+        }
+        LoginOutcome::AlreadyOnline => panic!("expected a login"),
+    }
+    assert_eq!(param(&transport, 3, "double_stack"), "1");
+    assert_eq!(param(&transport, 3, "ip"), "10.1.2.3");
+    assert_eq!(param(&transport6, 4, "ip"), "2001:db8::3");
+}
+
+#[test]
+fn full_login_keeps_ipv4_session_when_ipv6_fails() {
+    let client = dual_stack_client(offline_portal("10.1.2.3"), MemoryTransport::new());
+
+    match client.full_login(&LoginOptions::default()).unwrap() {
+        LoginOutcome::Completed {
+            response,
+            ipv6_response,
+            ..
+        } => {
+            assert_eq!(response.error, "ok");
+            assert!(matches!(
+                ipv6_response,
+                Some(Err(SRUNClientError::Transport(_)))
+            ));
+        }
+        LoginOutcome::AlreadyOnline => panic!("expected a login"),
+    }
+}
+
+#[test]
+fn full_login_can_leave_ipv6_session_alone() {
+    let transport6 = Arc::new(offline_portal("2001:db8::3"));
+    let client = dual_stack_client(offline_portal("10.1.2.3"), transport6.clone());
+    let mut options = LoginOptions::default();
+    options.skip_ipv6 = true;
+
+    match client.full_login(&options).unwrap() {
+        LoginOutcome::Completed { ipv6_response, .. } => assert!(ipv6_response.is_none()),
+        LoginOutcome::AlreadyOnline => panic!("expected a login"),
+    }
+    assert!(transport6.requests().is_empty());
+}
+
+#[test]
+fn full_logout_keeps_ipv4_logout_when_ipv6_fails() {
+    let transport = MemoryTransport::new()
+        .respond(
+            "/cgi-bin/rad_user_info",
+            HttpResponse::new(200, r#"cb({"error":"ok","online_ip":"10.1.2.3"})"#),
+        )
+        .respond(
+            "/index_1.html",
+            HttpResponse::new(302, "").with_header("Location", "/srun_portal_pc?ac_id=12"),
+        )
+        .respond(
+            "/cgi-bin/srun_portal",
+            HttpResponse::new(200, r#"cb({"error":"ok"})"#),
+        );
+    let client = dual_stack_client(transport, MemoryTransport::new());
+
+    let outcome = client.full_logout().unwrap();
+
+    assert_eq!(outcome.response.error, "ok");
+    assert!(matches!(
+        outcome.ipv6_response,
+        Some(Err(SRUNClientError::Transport(_)))
+    ));
+}
+
+/// A transport whose interface has lost its address.
+#[derive(Debug)]
+struct Unaddressed;
+
+impl Transport for Unaddressed {
+    fn execute(&self, _: &HttpRequest) -> Result<HttpResponse, SRUNClientError> {
+        Err(SRUNClientError::Transport("unreachable".into()))
+    }
+
+    fn refresh(&self) -> Result<bool, SRUNClientError> {
+        Err(SRUNClientError::Interface {
+            interface: String::from("eth0"),
+            message: String::from("no global IPv6 address"),
+        })
+    }
+}
+
+#[test]
+fn refresh_transport_leaves_ipv6_client_alone() {
+    let client = dual_stack_client(offline_portal("10.1.2.3"), Unaddressed);
+
+    assert!(!client.refresh_transport().unwrap());
+    assert!(client.ipv6().unwrap().refresh_transport().is_err());
+}